@@ -8,6 +8,10 @@ pub enum BorsCommand {
     Ping,
     /// Perform a try build.
     Try,
+    /// Perform a try build of the PR commits squashed into one.
+    TrySquash,
     /// Cancel a try build.
     TryCancel,
+    /// Show the effective configuration.
+    Config,
 }
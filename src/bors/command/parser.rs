@@ -30,8 +30,13 @@ impl CommandParser {
         text: &'a str,
     ) -> Vec<Result<BorsCommand, CommandParseError<'a>>> {
         // The order of the parsers in the vector is important
-        let parsers: Vec<fn(Tokenizer) -> ParseResult> =
-            vec![parser_ping, parser_try_cancel, parser_try];
+        let parsers: Vec<fn(Tokenizer) -> ParseResult> = vec![
+            parser_ping,
+            parser_config,
+            parser_try_cancel,
+            parser_try_squash,
+            parser_try,
+        ];
 
         text.lines()
             .filter_map(|line| match line.find(&self.prefix) {
@@ -79,6 +84,11 @@ fn parser_ping(tokenizer: Tokenizer) -> ParseResult {
     parse_exact("ping", BorsCommand::Ping, tokenizer)
 }
 
+/// Parses "@bors config".
+fn parser_config(tokenizer: Tokenizer) -> ParseResult {
+    parse_exact("config", BorsCommand::Config, tokenizer)
+}
+
 /// Parses "@bors try".
 fn parser_try(tokenizer: Tokenizer) -> ParseResult {
     parse_exact("try", BorsCommand::Try, tokenizer)
@@ -89,6 +99,11 @@ fn parser_try_cancel(tokenizer: Tokenizer) -> ParseResult {
     parse_list(&["try", "cancel"], BorsCommand::TryCancel, tokenizer)
 }
 
+/// Parses "@bors try squash".
+fn parser_try_squash(tokenizer: Tokenizer) -> ParseResult {
+    parse_list(&["try", "squash"], BorsCommand::TrySquash, tokenizer)
+}
+
 /// Returns either missing or unknown command error.
 fn parser_wildcard(mut tokenizer: Tokenizer) -> ParseResult {
     let result = match tokenizer.peek() {
@@ -179,6 +194,13 @@ mod tests {
         assert!(matches!(cmds[0], Ok(BorsCommand::Ping)));
     }
 
+    #[test]
+    fn test_parse_config() {
+        let cmds = parse_commands("@bors config");
+        assert_eq!(cmds.len(), 1);
+        assert!(matches!(cmds[0], Ok(BorsCommand::Config)));
+    }
+
     #[test]
     fn test_parse_command_multiline() {
         let cmds = parse_commands(
@@ -256,6 +278,13 @@ line two
         assert!(matches!(cmds[0], Ok(BorsCommand::TryCancel)));
     }
 
+    #[test]
+    fn test_parse_try_squash() {
+        let cmds = parse_commands("@bors try squash");
+        assert_eq!(cmds.len(), 1);
+        assert!(matches!(cmds[0], Ok(BorsCommand::TrySquash)));
+    }
+
     fn parse_commands(text: &str) -> Vec<Result<BorsCommand, CommandParseError>> {
         CommandParser::new("@bors").parse_commands(text)
     }
@@ -1,11 +1,12 @@
+use serde::{Deserialize, Serialize};
 use url::Url;
 
 use crate::github::client::GitHubClient;
 use crate::github::misc::{WorkflowStatus, WorkflowType};
 use crate::github::{CommitSha, GithubRepo, GithubUser, PullRequest, PullRequestNumber};
-use crate::models::RunId;
+use crate::models::{Installation, RunId};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum BorsEvent {
     /// A comment was posted on a pull request.
     Comment(PullRequestComment),
@@ -17,12 +18,22 @@ pub enum BorsEvent {
     /// workflow from some external CI system.
     CheckSuiteCompleted(CheckSuiteCompleted),
     /// The configuration of some repository has been changed for the bot's Github App.
-    InstallationsChanged,
+    InstallationsChanged(InstallationsChanged),
+    /// GitHub merge queue created a merge group and wants its checks to run.
+    MergeGroupChecksRequested(MergeGroup),
+    /// A merge group of GitHub merge queue was merged or discarded.
+    MergeGroupDestroyed(MergeGroupDestroyed),
+    /// Commits were pushed to a branch (e.g. merge queue merged its groups).
+    BranchPushed(BranchPushed),
+    /// A pull request was opened or its branch was updated.
+    PullRequestUpdated(PullRequestUpdated),
+    /// A pull request was closed or merged.
+    PullRequestClosed(PullRequestClosed),
     /// Periodic event that serves for checking e.g. timeouts.
     Refresh,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum PR {
     // PR needs to be fetched
     PRId((GithubRepo, PullRequestNumber)),
@@ -46,7 +57,7 @@ impl PR {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PullRequestComment {
     pub repository: GithubRepo,
     pub author: GithubUser,
@@ -55,7 +66,7 @@ pub struct PullRequestComment {
     pub text: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WorkflowStarted {
     pub repository: GithubRepo,
     pub name: String,
@@ -66,7 +77,7 @@ pub struct WorkflowStarted {
     pub url: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WorkflowCompleted {
     pub repository: GithubRepo,
     pub branch: String,
@@ -75,9 +86,76 @@ pub struct WorkflowCompleted {
     pub status: WorkflowStatus,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CheckSuiteCompleted {
     pub repository: GithubRepo,
     pub branch: String,
     pub commit_sha: CommitSha,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstallationsChanged {
+    pub installation: Installation,
+    /// Repositories that the app has gained access to.
+    pub added: Vec<GithubRepo>,
+    /// Repositories that the app has lost access to.
+    pub removed: Vec<GithubRepo>,
+    /// The whole installation was removed or suspended.
+    pub uninstalled: bool,
+}
+
+/// Group of pull requests tested together by GitHub merge queue
+/// on a `gh-readonly-queue/{base}/pr-{number}-{sha}` branch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergeGroup {
+    pub repository: GithubRepo,
+    /// Branch of the group (without `refs/heads/`).
+    pub head_ref: String,
+    pub head_sha: CommitSha,
+    /// Branch the group will be merged into (without `refs/heads/`).
+    pub base_ref: String,
+    /// Parent commit of the group, the head of the group ahead of it in the queue (if any).
+    pub base_sha: CommitSha,
+    /// The last pull request of the group (the one the group was created for).
+    pub pull_request: Option<PullRequestNumber>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MergeGroupDestroyedReason {
+    /// The group was merged into the base branch.
+    Merged,
+    /// The group was discarded because of a change in the queue ahead of it.
+    Invalidated,
+    /// A pull request of the group was removed from the queue.
+    Dequeued,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergeGroupDestroyed {
+    pub group: MergeGroup,
+    pub reason: MergeGroupDestroyedReason,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BranchPushed {
+    pub repository: GithubRepo,
+    pub branch: String,
+    /// Pushed commits, the last one is the new head of the branch.
+    pub commits: Vec<CommitSha>,
+    /// Files added or modified by the pushed commits.
+    pub changed_files: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PullRequestUpdated {
+    pub repository: GithubRepo,
+    pub number: PullRequestNumber,
+    /// New head of the pull request branch.
+    pub head_sha: CommitSha,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PullRequestClosed {
+    pub repository: GithubRepo,
+    pub number: PullRequestNumber,
+}
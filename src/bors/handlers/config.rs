@@ -0,0 +1,149 @@
+use super::{check_permissions, PullRequestData};
+use crate::config::{Config, ConfigDiagnostic, ConfigError, CONFIG_FILE_PATH};
+use crate::github::client::GitHubClient;
+use crate::github::{CommitSha, GithubRepo, PullRequestNumber};
+use crate::models::checks::{AnnotationLevel, CheckRunAnnotation, CheckRunOutput};
+use crate::models::pulls::FileDiffStatus;
+use crate::permissions::PermissionType;
+
+/// Name of the check run reporting problems of the config file.
+const CONFIG_CHECK_RUN: &str = "bors config";
+
+/// Shows the effective config of the repository (after inheritance).
+///
+/// The organization config may not be public, so only try users (and reviewers) can see it.
+pub(super) async fn command_config<C: GitHubClient>(
+    client: &mut C,
+    pr_data: &PullRequestData,
+) -> anyhow::Result<()> {
+    let repo = &pr_data.repository;
+    let config = match Config::get_all(client, repo).await {
+        Ok(config) => config,
+        Err(error @ (ConfigError::Missing(_) | ConfigError::Invalid { .. })) => {
+            return client
+                .post_comment(repo, pr_data.number, &format!(":x: {error}"))
+                .await;
+        }
+        Err(error) => return Err(error.into()),
+    };
+    if !check_permissions(client, pr_data, &config, PermissionType::Try).await? {
+        return Ok(());
+    }
+    let text = match Config::get_all_annotated(client, repo).await {
+        Ok(config) => format!(
+            "Effective config of `{repo}`:\n<details><summary>{CONFIG_FILE_PATH}</summary>\n\n```toml\n{config}```\n\n</details>"
+        ),
+        Err(error @ (ConfigError::Missing(_) | ConfigError::Invalid { .. })) => {
+            format!(":x: {error}")
+        }
+        Err(error) => return Err(error.into()),
+    };
+    client.post_comment(repo, pr_data.number, &text).await
+}
+
+/// Validates the config file if the pull request changes it.
+pub(super) async fn handle_pull_request_updated<C: GitHubClient>(
+    client: &mut C,
+    repo: &GithubRepo,
+    pr: PullRequestNumber,
+    head_sha: &CommitSha,
+) -> anyhow::Result<()> {
+    let files = client.get_pull_request_files(repo, pr).await?;
+    if files
+        .iter()
+        .any(|file| file.filename == CONFIG_FILE_PATH && file.status != FileDiffStatus::Removed)
+    {
+        validate_config(client, repo, head_sha).await?;
+    }
+    Ok(())
+}
+
+/// Validates the config file on the commit and reports the result as a check run.
+pub(super) async fn validate_config<C: GitHubClient>(
+    client: &mut C,
+    repo: &GithubRepo,
+    sha: &CommitSha,
+) -> anyhow::Result<()> {
+    // The commit does not change, so a retried event does not need to report it again
+    if client
+        .get_check_runs(repo, sha)
+        .await?
+        .iter()
+        .any(|run| run.name == CONFIG_CHECK_RUN)
+    {
+        tracing::debug!("Config on {sha} was already validated");
+        return Ok(());
+    }
+    let Some(text) = client
+        .get_file_content(repo, CONFIG_FILE_PATH, sha.as_ref())
+        .await?
+    else {
+        tracing::warn!("Cannot find {CONFIG_FILE_PATH} on {repo}@{sha}");
+        return Ok(());
+    };
+    let default_branch = client
+        .get_repository(repo)
+        .await?
+        .default_branch
+        .unwrap_or_else(|| "master".to_string());
+    let validation = Config::validate(&text, &default_branch);
+    tracing::info!(
+        "Config on {sha} has {} errors and {} warnings",
+        validation.errors.len(),
+        validation.warnings.len()
+    );
+
+    let (conclusion, title) = if !validation.errors.is_empty() {
+        ("failure", "Invalid config")
+    } else if !validation.warnings.is_empty() {
+        ("neutral", "Config has unknown keys")
+    } else {
+        ("success", "Config is valid")
+    };
+    let diagnostics = validation
+        .errors
+        .iter()
+        .map(|diagnostic| (AnnotationLevel::Failure, diagnostic))
+        .chain(
+            validation
+                .warnings
+                .iter()
+                .map(|diagnostic| (AnnotationLevel::Warning, diagnostic)),
+        );
+    let mut summary = Vec::new();
+    let mut annotations = Vec::new();
+    for (level, ConfigDiagnostic { line, message }) in diagnostics {
+        match line {
+            Some(line) => {
+                summary.push(format!("- `{CONFIG_FILE_PATH}:{line}`: {message}"));
+                annotations.push(CheckRunAnnotation {
+                    path: CONFIG_FILE_PATH.to_owned(),
+                    start_line: *line,
+                    end_line: *line,
+                    annotation_level: level,
+                    message: message.clone(),
+                });
+            }
+            None => summary.push(format!("- `{CONFIG_FILE_PATH}`: {message}")),
+        }
+    }
+    let summary = if summary.is_empty() {
+        format!("`{CONFIG_FILE_PATH}` can be loaded by bors.")
+    } else {
+        summary.join("\n")
+    };
+
+    client
+        .create_check_run(
+            repo,
+            sha,
+            CONFIG_CHECK_RUN,
+            conclusion,
+            &CheckRunOutput {
+                title: title.to_owned(),
+                summary,
+                annotations,
+            },
+        )
+        .await
+}
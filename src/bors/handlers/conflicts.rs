@@ -0,0 +1,239 @@
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use worker::Delay;
+
+use super::approvers;
+use super::labels::{handle_label_trigger, revert_label_trigger};
+use crate::config::Config;
+use crate::github::client::GitHubClient;
+use crate::github::{GithubRepo, LabelTrigger, PullRequestNumber};
+use crate::store::{
+    merge_conflict_key, pull_request_key, queued_pull_request_key, QueuedPullRequest,
+    ReportedMergeConflict, Store, MERGE_CONFLICT_TTL, PULL_REQUEST_PREFIX,
+    QUEUED_PULL_REQUEST_PREFIX,
+};
+
+/// Time (in seconds) for GitHub to compute mergeability after a push.
+///
+/// Must stay well below the 30 seconds that `wait_until` work may run after the response.
+const MERGEABLE_DELAY: u64 = 5;
+
+/// What to do about a PR after reading its mergeability.
+#[derive(Debug, PartialEq)]
+enum ConflictAction {
+    /// GitHub has not computed the mergeability yet, check again later.
+    Retry,
+    /// The PR started to conflict, tell the author (if the PR is approved or queued).
+    Report,
+    /// The reported conflict is gone, remove the labels.
+    Revert,
+    /// Nothing changed since the last check.
+    Nothing,
+}
+
+fn conflict_action(mergeable: Option<bool>, reported: bool) -> ConflictAction {
+    match (mergeable, reported) {
+        (None, _) => ConflictAction::Retry,
+        (Some(true), true) => ConflictAction::Revert,
+        (Some(false), false) => ConflictAction::Report,
+        (Some(true), false) | (Some(false), true) => ConflictAction::Nothing,
+    }
+}
+
+enum Mergeability {
+    /// The PR is not open against the pushed branch.
+    Skipped,
+    /// GitHub has not computed it yet.
+    Unknown,
+    Known,
+}
+
+/// Tells authors of approved or queued PRs that their PR conflicts
+/// with the base branch after it was pushed to.
+///
+/// Every conflict is reported only once, until the PR becomes mergeable again.
+pub(super) async fn handle_base_branch_pushed<C: GitHubClient>(
+    client: &mut C,
+    store: &Store,
+    repo: &GithubRepo,
+    branch: &str,
+) -> anyhow::Result<()> {
+    // Branches of the merge queue are not base branches
+    if branch.starts_with("gh-readonly-queue/") {
+        return Ok(());
+    }
+    let candidates = tracked_pull_requests(store, repo).await?;
+    if candidates.is_empty() {
+        return Ok(());
+    }
+    let config = Config::get_all(client, repo).await?;
+    // Neither are the try branches of bors
+    if branch == config.try_branch() || branch == config.try_merge_branch() {
+        return Ok(());
+    }
+
+    let mut unknown = Vec::new();
+    for pr in candidates {
+        if let Mergeability::Unknown =
+            check_merge_conflict(client, store, &config, repo, branch, pr).await?
+        {
+            unknown.push(pr);
+        }
+    }
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    // GitHub computes mergeability in the background, give it some time
+    Delay::from(Duration::from_secs(MERGEABLE_DELAY)).await;
+    for pr in unknown {
+        if let Mergeability::Unknown =
+            check_merge_conflict(client, store, &config, repo, branch, pr).await?
+        {
+            tracing::info!("Mergeability of #{pr} is still unknown");
+        }
+    }
+    Ok(())
+}
+
+/// Clears a reported conflict once the author pushes to the PR, it is checked
+/// again on the next push to the base branch.
+pub(super) async fn handle_pull_request_pushed<C: GitHubClient>(
+    client: &mut C,
+    store: &Store,
+    repo: &GithubRepo,
+    pr: PullRequestNumber,
+) -> anyhow::Result<()> {
+    let key = merge_conflict_key(repo, pr);
+    if store.get::<ReportedMergeConflict>(&key).await?.is_none() {
+        return Ok(());
+    }
+    tracing::info!("#{pr} was updated after its merge conflict was reported");
+    let config = Config::get_all(client, repo).await?;
+    revert_label_trigger(client, repo, pr, &config, LabelTrigger::MergeConflict).await?;
+    store.delete(&key).await
+}
+
+/// Forgets a closed (or merged) pull request, so that it is no longer checked for conflicts.
+pub(super) async fn handle_pull_request_closed(
+    store: &Store,
+    repo: &GithubRepo,
+    pr: PullRequestNumber,
+) -> anyhow::Result<()> {
+    tracing::debug!("Forgetting closed #{pr}");
+    store.delete(&pull_request_key(repo, pr)).await?;
+    store.delete(&merge_conflict_key(repo, pr)).await
+}
+
+/// PRs of the repository that bors knows about (e.g. from a try build) or that are queued.
+///
+/// Closed PRs are forgotten, see [`handle_pull_request_closed`].
+async fn tracked_pull_requests(
+    store: &Store,
+    repo: &GithubRepo,
+) -> anyhow::Result<BTreeSet<PullRequestNumber>> {
+    let mut prs = BTreeSet::new();
+    for prefix in [PULL_REQUEST_PREFIX, QUEUED_PULL_REQUEST_PREFIX] {
+        let prefix = format!("{prefix}{repo}#");
+        for key in store.keys(&prefix).await? {
+            match key[prefix.len()..].parse() {
+                Ok(pr) => {
+                    prs.insert(pr);
+                }
+                Err(error) => tracing::warn!("Invalid key {key}: {error}"),
+            }
+        }
+    }
+    Ok(prs)
+}
+
+/// Queued PRs were approved, others need an approving review.
+async fn is_approved_or_queued<C: GitHubClient>(
+    client: &mut C,
+    store: &Store,
+    repo: &GithubRepo,
+    pr: PullRequestNumber,
+) -> anyhow::Result<bool> {
+    let queued_key = queued_pull_request_key(repo, pr);
+    if store.get::<QueuedPullRequest>(&queued_key).await?.is_some() {
+        return Ok(true);
+    }
+    let reviews = client.get_pull_request_reviews(repo, pr).await?;
+    Ok(!approvers(reviews).is_empty())
+}
+
+async fn check_merge_conflict<C: GitHubClient>(
+    client: &mut C,
+    store: &Store,
+    config: &Config,
+    repo: &GithubRepo,
+    branch: &str,
+    pr: PullRequestNumber,
+) -> anyhow::Result<Mergeability> {
+    let pull = client.get_pull_request(repo, pr).await?;
+    if !pull.open {
+        // The close event was missed (or came before bors tracked closed PRs)
+        handle_pull_request_closed(store, repo, pr).await?;
+        return Ok(Mergeability::Skipped);
+    }
+    if pull.base.name != branch {
+        return Ok(Mergeability::Skipped);
+    }
+    let key = merge_conflict_key(repo, pr);
+    let reported = store.get::<ReportedMergeConflict>(&key).await?.is_some();
+    match conflict_action(pull.mergeable, reported) {
+        ConflictAction::Retry => return Ok(Mergeability::Unknown),
+        ConflictAction::Revert => {
+            tracing::info!("Merge conflict of #{pr} was resolved");
+            store.delete(&key).await?;
+            revert_label_trigger(client, repo, pr, config, LabelTrigger::MergeConflict).await?;
+        }
+        ConflictAction::Report => {
+            if is_approved_or_queued(client, store, repo, pr).await? {
+                tracing::info!("#{pr} conflicts with {branch}");
+                client
+                    .post_comment(
+                        repo,
+                        pr,
+                        &config.messages.merge_conflict(pr, &pull.head.name, branch),
+                    )
+                    .await?;
+                handle_label_trigger(client, repo, pr, config, LabelTrigger::MergeConflict).await?;
+                store
+                    .put_with_ttl(
+                        &key,
+                        &ReportedMergeConflict {
+                            base: branch.to_owned(),
+                        },
+                        MERGE_CONFLICT_TTL,
+                    )
+                    .await?;
+            }
+        }
+        ConflictAction::Nothing => {}
+    }
+    Ok(Mergeability::Known)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{conflict_action, ConflictAction};
+
+    #[test]
+    fn conflict_reported_once() {
+        assert_eq!(conflict_action(Some(false), false), ConflictAction::Report);
+        assert_eq!(conflict_action(Some(false), true), ConflictAction::Nothing);
+    }
+
+    #[test]
+    fn resolved_conflict_reverted() {
+        assert_eq!(conflict_action(Some(true), true), ConflictAction::Revert);
+        assert_eq!(conflict_action(Some(true), false), ConflictAction::Nothing);
+    }
+
+    #[test]
+    fn unknown_mergeability_retried() {
+        assert_eq!(conflict_action(None, false), ConflictAction::Retry);
+        assert_eq!(conflict_action(None, true), ConflictAction::Retry);
+    }
+}
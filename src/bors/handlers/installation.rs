@@ -0,0 +1,113 @@
+use crate::bors::event::InstallationsChanged;
+use crate::config::{Config, ConfigError};
+use crate::github::client::GitHubClient;
+use crate::github::GithubRepo;
+use crate::store::{
+    installed_repository_key, setup_issue_key, InstalledRepository, SetupIssue, Store,
+    INSTALLED_REPOSITORY_PREFIX, SETUP_ISSUE_TTL,
+};
+use crate::utils::time::now;
+
+const SETUP_ISSUE_TITLE: &str = "Finish setting up bors";
+
+/// Keeps the list of installed repositories in KV up to date and checks
+/// that newly added repositories are ready to be used with the bot.
+pub(super) async fn handle_installations_changed<C: GitHubClient>(
+    client: &mut C,
+    store: &Store,
+    payload: InstallationsChanged,
+) -> anyhow::Result<()> {
+    let installation = payload.installation.id;
+    if payload.uninstalled && payload.removed.is_empty() {
+        // Suspend events do not list repositories, so forget everything of this installation
+        for key in store.keys(INSTALLED_REPOSITORY_PREFIX).await? {
+            if let Some(InstalledRepository { installation: id }) = store.get(&key).await? {
+                if id == installation {
+                    store.delete(&key).await?;
+                }
+            }
+        }
+    }
+    for repo in &payload.removed {
+        tracing::info!("Repository {repo} removed from installation {installation}");
+        store.delete(&installed_repository_key(repo)).await?;
+    }
+
+    for repo in &payload.added {
+        tracing::info!("Repository {repo} added to installation {installation}");
+        store
+            .put(
+                &installed_repository_key(repo),
+                &InstalledRepository { installation },
+            )
+            .await?;
+        if let Err(error) = check_repository_setup(client, store, repo).await {
+            tracing::warn!("Cannot check setup of {repo}: {error:?}");
+        }
+    }
+    Ok(())
+}
+
+/// Opens an issue in the repository if it is missing a config or merge queue.
+///
+/// Repositories are added again e.g. when the installation is unsuspended,
+/// so the issue is opened only once.
+async fn check_repository_setup<C: GitHubClient>(
+    client: &mut C,
+    store: &Store,
+    repo: &GithubRepo,
+) -> anyhow::Result<()> {
+    let key = setup_issue_key(repo);
+    if let Some(issue) = store.get::<SetupIssue>(&key).await? {
+        tracing::debug!("Setup issue of {repo} was opened at {}", issue.opened_at);
+        return Ok(());
+    }
+    let mut missing = Vec::new();
+
+    match Config::get_all(client, repo).await {
+        Ok(_) => {}
+        Err(ConfigError::Missing(_)) => missing.push(
+            "- [ ] Add a `bors-mq.toml` config file to the root of the repository.".to_string(),
+        ),
+        Err(ConfigError::Invalid { error, .. }) => missing.push(format!(
+            "- [ ] Fix the `bors-mq.toml` config file:\n```\n{error}\n```"
+        )),
+        Err(error) => return Err(error.into()),
+    }
+
+    let default_branch = client
+        .get_repository(repo)
+        .await?
+        .default_branch
+        .unwrap_or_else(|| "master".to_string());
+    let rules = client.get_branch_rules(repo, &default_branch).await?;
+    if !rules.iter().any(|rule| rule.rule_type == "merge_queue") {
+        missing.push(format!(
+            "- [ ] Require a merge queue for the `{default_branch}` branch (in a branch ruleset)."
+        ));
+    }
+
+    if missing.is_empty() {
+        tracing::info!("Repository {repo} is set up");
+        return Ok(());
+    }
+
+    tracing::info!("Repository {repo} is not fully set up, opening an issue");
+    client
+        .create_issue(
+            repo,
+            SETUP_ISSUE_TITLE,
+            &format!(
+                r#"Thanks for installing bors! :wave:
+
+Before it can manage pull requests of this repository, please:
+
+{}"#,
+                missing.join("\n")
+            ),
+        )
+        .await?;
+    store
+        .put_with_ttl(&key, &SetupIssue { opened_at: now() }, SETUP_ISSUE_TTL)
+        .await
+}
@@ -0,0 +1,56 @@
+use crate::config::Config;
+use crate::github::client::GitHubClient;
+use crate::github::{GithubRepo, LabelModification, LabelTrigger, PullRequestNumber};
+
+/// Applies the label modifications configured for the trigger.
+pub(super) async fn handle_label_trigger<C: GitHubClient>(
+    client: &mut C,
+    repo: &GithubRepo,
+    pr: PullRequestNumber,
+    config: &Config,
+    trigger: LabelTrigger,
+) -> anyhow::Result<()> {
+    let Some(modifications) = config.labels.get(&trigger) else {
+        return Ok(());
+    };
+    let (add, remove) = split_modifications(modifications);
+    tracing::debug!("{trigger:?}: adding labels {add:?}, removing labels {remove:?}");
+    if !add.is_empty() {
+        client.add_labels(repo, pr, &add).await?;
+    }
+    if !remove.is_empty() {
+        client.remove_labels(repo, pr, &remove).await?;
+    }
+    Ok(())
+}
+
+/// Removes the labels added by the trigger, once its cause is gone.
+pub(super) async fn revert_label_trigger<C: GitHubClient>(
+    client: &mut C,
+    repo: &GithubRepo,
+    pr: PullRequestNumber,
+    config: &Config,
+    trigger: LabelTrigger,
+) -> anyhow::Result<()> {
+    let Some(modifications) = config.labels.get(&trigger) else {
+        return Ok(());
+    };
+    let (add, _) = split_modifications(modifications);
+    tracing::debug!("{trigger:?} reverted: removing labels {add:?}");
+    if !add.is_empty() {
+        client.remove_labels(repo, pr, &add).await?;
+    }
+    Ok(())
+}
+
+fn split_modifications(modifications: &[LabelModification]) -> (Vec<String>, Vec<String>) {
+    let mut add = Vec::new();
+    let mut remove = Vec::new();
+    for modification in modifications {
+        match modification {
+            LabelModification::Add(label) => add.push(label.clone()),
+            LabelModification::Remove(label) => remove.push(label.clone()),
+        }
+    }
+    (add, remove)
+}
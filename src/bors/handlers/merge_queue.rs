@@ -0,0 +1,273 @@
+use super::approvers;
+use crate::bors::event::{
+    BranchPushed, MergeGroup, MergeGroupDestroyed, MergeGroupDestroyedReason,
+};
+use crate::config::Config;
+use crate::github::client::GitHubClient;
+use crate::github::{CommitSha, GithubRepo, PullRequestNumber};
+use crate::messages::Messages;
+use crate::store::{
+    merge_group_key, queued_pull_request_key, QueuedMergeGroup, QueuedPullRequest, Store,
+    MERGE_QUEUE_TTL,
+};
+use crate::utils::time::{format_duration, now};
+
+/// Conclusions of check runs that make a merge group fail.
+const FAILED_CONCLUSIONS: &[&str] = &["failure", "timed_out", "cancelled", "action_required"];
+
+/// Upper bound of pull requests looked up in one merge group.
+const MAX_GROUP_SIZE: usize = 100;
+
+/// Remembers the merge group, so that its PR can be found once the group is merged.
+pub(super) async fn handle_merge_group_checks_requested(
+    store: &Store,
+    group: MergeGroup,
+) -> anyhow::Result<()> {
+    let Some(pr) = group.pull_request else {
+        tracing::warn!("Cannot find the PR of merge group {}", group.head_ref);
+        return Ok(());
+    };
+    tracing::info!(
+        "Merge group {} of PR #{pr} waits for checks",
+        group.head_ref
+    );
+    store
+        .put_with_ttl(
+            &merge_group_key(&group.repository, &group.head_sha),
+            &QueuedMergeGroup {
+                pull_request: pr,
+                base_sha: Some(group.base_sha.clone()),
+            },
+            MERGE_QUEUE_TTL,
+        )
+        .await?;
+
+    // Invalidated groups are recreated, keep the time the PR was queued first
+    let key = queued_pull_request_key(&group.repository, pr);
+    if store.get::<QueuedPullRequest>(&key).await?.is_none() {
+        store
+            .put_with_ttl(
+                &key,
+                &QueuedPullRequest { enqueued_at: now() },
+                MERGE_QUEUE_TTL,
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+/// Tells the pull request why it left the merge queue.
+pub(super) async fn handle_merge_group_destroyed<C: GitHubClient>(
+    client: &mut C,
+    store: &Store,
+    payload: MergeGroupDestroyed,
+) -> anyhow::Result<()> {
+    let group = payload.group;
+    let (reason, discarded) = match payload.reason {
+        MergeGroupDestroyedReason::Merged => {
+            tracing::info!("Merge group {} was merged", group.head_ref);
+            return Ok(());
+        }
+        MergeGroupDestroyedReason::Invalidated => (
+            "its merge group was invalidated (the base branch or a pull request ahead of it in the queue has changed)",
+            "invalidated",
+        ),
+        MergeGroupDestroyedReason::Dequeued => (
+            "it was removed from the merge queue",
+            "removed from the merge queue",
+        ),
+    };
+    let Some(pr) = group.pull_request else {
+        tracing::warn!("Cannot find the PR of merge group {}", group.head_ref);
+        return Ok(());
+    };
+    let ahead = pull_requests_ahead(store, &group.repository, &group.base_sha).await?;
+    let failed = match client
+        .get_check_runs(&group.repository, &group.head_sha)
+        .await
+    {
+        Ok(runs) => runs
+            .into_iter()
+            .filter(|run| {
+                run.conclusion
+                    .as_deref()
+                    .is_some_and(|conclusion| FAILED_CONCLUSIONS.contains(&conclusion))
+            })
+            .map(|run| match run.html_url {
+                Some(url) => format!("- [{}]({url})", run.name),
+                None => format!("- {}", run.name),
+            })
+            .collect(),
+        Err(error) => {
+            tracing::warn!("Cannot get check runs of {}: {error:?}", group.head_sha);
+            Vec::new()
+        }
+    };
+    let checks = if failed.is_empty() {
+        format!(
+            "See the [checks of the merge group](https://github.com/{}/commit/{}/checks).",
+            group.repository, group.head_sha
+        )
+    } else {
+        format!("Failed checks:\n{}", failed.join("\n"))
+    };
+
+    let messages = cached_messages(client, &group.repository).await;
+    client
+        .post_comment(
+            &group.repository,
+            pr,
+            &messages.merge_queue_left(pr, reason, &checks),
+        )
+        .await?;
+    for other in ahead {
+        client
+            .post_comment(
+                &group.repository,
+                other,
+                &messages.merge_group_discarded(pr, discarded, &checks),
+            )
+            .await?;
+    }
+
+    // Only forget the group once the PR was told, so that a retry can still report it
+    store
+        .delete(&merge_group_key(&group.repository, &group.head_sha))
+        .await?;
+    if payload.reason == MergeGroupDestroyedReason::Dequeued {
+        store
+            .delete(&queued_pull_request_key(&group.repository, pr))
+            .await?;
+    }
+    Ok(())
+}
+
+/// Pull requests of the groups ahead of a merge group, which are tested in it as well.
+///
+/// Every group is based on the head of the group ahead of it,
+/// so the chain of stored groups is followed from `base_sha` until the base branch is reached.
+async fn pull_requests_ahead(
+    store: &Store,
+    repo: &GithubRepo,
+    base_sha: &CommitSha,
+) -> anyhow::Result<Vec<PullRequestNumber>> {
+    let mut prs = Vec::new();
+    let mut sha = base_sha.clone();
+    while prs.len() < MAX_GROUP_SIZE {
+        let Some(group) = store
+            .get::<QueuedMergeGroup>(&merge_group_key(repo, &sha))
+            .await?
+        else {
+            break;
+        };
+        prs.push(group.pull_request);
+        match group.base_sha {
+            Some(base_sha) => sha = base_sha,
+            None => break,
+        }
+    }
+    Ok(prs)
+}
+
+/// Reports success on every PR whose merge group was pushed to the base branch.
+pub(super) async fn handle_branch_pushed<C: GitHubClient>(
+    client: &mut C,
+    store: &Store,
+    push: BranchPushed,
+) -> anyhow::Result<()> {
+    for sha in &push.commits {
+        let key = merge_group_key(&push.repository, sha);
+        let Some(QueuedMergeGroup { pull_request, .. }) = store.get(&key).await? else {
+            continue;
+        };
+        let queued_key = queued_pull_request_key(&push.repository, pull_request);
+        let queued: Option<QueuedPullRequest> = store.get(&queued_key).await?;
+
+        tracing::info!("PR #{pull_request} was merged into {}", push.branch);
+        let messages = cached_messages(client, &push.repository).await;
+        let message = test_successful_message(
+            client,
+            &messages,
+            &push.repository,
+            &push.branch,
+            sha,
+            pull_request,
+            queued,
+        )
+        .await;
+        client
+            .post_comment(&push.repository, pull_request, &message)
+            .await?;
+
+        // Only forget the PR once it was told, so that a retry can still report it
+        store.delete(&key).await?;
+        store.delete(&queued_key).await?;
+    }
+    Ok(())
+}
+
+/// Messages of the last loaded config of the repository, merge queue events
+/// are not worth loading the config from GitHub.
+async fn cached_messages<C: GitHubClient>(client: &C, repo: &GithubRepo) -> Messages {
+    Config::get_all_cached(client.cache(), repo)
+        .await
+        .map(|config| config.messages)
+        .unwrap_or_default()
+}
+
+/// Homu-style summary of a merged pull request.
+async fn test_successful_message<C: GitHubClient>(
+    client: &mut C,
+    messages: &Messages,
+    repo: &GithubRepo,
+    branch: &str,
+    sha: &CommitSha,
+    pr: PullRequestNumber,
+    queued: Option<QueuedPullRequest>,
+) -> String {
+    let workflows = match client.get_check_runs(repo, sha).await {
+        Ok(runs) => runs
+            .into_iter()
+            .map(|run| {
+                let name = match run.html_url {
+                    Some(url) => format!("[{}]({url})", run.name),
+                    None => run.name,
+                };
+                match (run.started_at, run.completed_at) {
+                    (Some(started), Some(completed)) => {
+                        format!("{name} ({})", format_duration(completed - started))
+                    }
+                    _ => name,
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", "),
+        Err(error) => {
+            tracing::warn!("Cannot get check runs of {sha}: {error:?}");
+            String::new()
+        }
+    };
+    let approvers = match client.get_pull_request_reviews(repo, pr).await {
+        Ok(reviews) => approvers(reviews)
+            .iter()
+            .map(|login| format!("@{login}"))
+            .collect::<Vec<_>>()
+            .join(", "),
+        Err(error) => {
+            tracing::warn!("Cannot get reviews of #{pr}: {error:?}");
+            String::new()
+        }
+    };
+
+    let time_in_queue = queued
+        .map(|queued| format_duration(now() - queued.enqueued_at))
+        .unwrap_or_default();
+    messages.merge_queue_merged(
+        pr,
+        sha.as_ref(),
+        branch,
+        &workflows,
+        &approvers,
+        &time_in_queue,
+    )
+}
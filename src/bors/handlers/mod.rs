@@ -1,22 +1,46 @@
+use std::collections::{BTreeSet, HashMap};
+
 use anyhow::Context;
+use reqwest::StatusCode;
 use tracing::Instrument;
 
 use super::event::PR;
 use super::CommandParser;
 use crate::bors::command::{BorsCommand, CommandParseError};
 use crate::bors::event::{BorsEvent, PullRequestComment};
+use crate::bors::handlers::config::{command_config, handle_pull_request_updated, validate_config};
+use crate::bors::handlers::conflicts::{
+    handle_base_branch_pushed, handle_pull_request_closed, handle_pull_request_pushed,
+};
+use crate::bors::handlers::installation::handle_installations_changed;
+use crate::bors::handlers::merge_queue::{
+    handle_branch_pushed, handle_merge_group_checks_requested, handle_merge_group_destroyed,
+};
 use crate::bors::handlers::ping::command_ping;
-use crate::bors::handlers::trybuild::{command_try_build, command_try_cancel, TRY_BRANCH_NAME};
+use crate::bors::handlers::refresh::handle_refresh;
+use crate::bors::handlers::trybuild::{command_try_build, command_try_cancel};
 /*use crate::bors::handlers::workflow::{
     handle_check_suite_completed, handle_workflow_completed, handle_workflow_started,
 };*/
-use crate::config::CMD_PREFIX;
-use crate::config::PAT;
-use crate::github::client::{AutoGitHubClient, GitHubClient, TokenClient};
-use crate::github::{GithubRepo, GithubUser, PullRequestNumber};
+use crate::config::{Config, CMD_PREFIX, CONFIG_FILE_PATH, PAT};
+use crate::github::client::{AutoClientError, AutoGitHubClient, GitHubClient, TokenClient};
+use crate::github::{
+    BranchUpdateError, GithubRepo, GithubUser, MergeError, PullRequestNumber, StatusError,
+};
+use crate::models::pulls::{Review, ReviewState};
+use crate::permissions::{
+    GitHubPermissionResolver, PermissionDecision, PermissionResolver, PermissionType,
+};
+use crate::store::{Store, StoreError};
 use crate::utils::logging::LogError;
 
+mod config;
+mod conflicts;
+mod installation;
+mod labels;
+mod merge_queue;
 mod ping;
+mod refresh;
 mod trybuild;
 //mod workflow;
 
@@ -27,29 +51,187 @@ pub struct PullRequestData {
     pub pr: PR,
 }
 
+/// Checks that the author of the command has the permission,
+/// otherwise explains why not on the PR.
+///
+/// Every decision is logged for auditing.
+async fn check_permissions<C: GitHubClient>(
+    client: &mut C,
+    pr_data: &PullRequestData,
+    config: &Config,
+    permission: PermissionType,
+) -> anyhow::Result<bool> {
+    let username = &pr_data.author.username;
+    let decision = GitHubPermissionResolver::new(client, &pr_data.repository, config)
+        .check_permission(username, permission)
+        .await;
+    match decision {
+        PermissionDecision::Granted(reason) => {
+            tracing::info!(?permission, ?reason, "Permission granted to {username}");
+            Ok(true)
+        }
+        PermissionDecision::Denied(reason) => {
+            tracing::info!(?permission, ?reason, "Permission denied to {username}");
+            client
+                .post_comment(
+                    &pr_data.repository,
+                    pr_data.number,
+                    &config.messages.permission_denied(username, &reason),
+                )
+                .await?;
+            Ok(false)
+        }
+    }
+}
+
 /// This function performs a single BORS event, it is the main execution function of the bot.
-pub async fn handle_bors_event(event: BorsEvent) -> anyhow::Result<()> {
+pub async fn handle_bors_event(event: BorsEvent, store: &Store) -> anyhow::Result<()> {
+    // Errors are logged in the span of their handler, the caller only learns whether
+    // the event is worth retrying. Permanent errors would just fail again.
+    let mut retry = false;
     match event {
         BorsEvent::Comment(comment) => {
+            let mut client = AutoGitHubClient::new(store.clone());
             // We want to ignore comments made by this bot
-            /*if state.is_comment_internal(&comment) {
+            if is_comment_internal(&mut client, &comment).await {
                 tracing::trace!("Ignoring comment {comment:?} because it was authored by this bot");
                 return Ok(());
-            }*/
+            }
 
             let span = tracing::info_span!(
                 "Comment",
                 pr = format!("{}#{}", comment.repository, comment.pr_number),
                 author = comment.author.username
             );
-            if let Err(error) = handle_comment(comment).instrument(span.clone()).await {
+            if let Err(error) = handle_comment(&mut client, store, comment)
+                .instrument(span.clone())
+                .await
+            {
+                retry |= is_transient(&error);
+                span.log_error(error);
+            }
+        }
+        BorsEvent::InstallationsChanged(payload) => {
+            let span = tracing::info_span!(
+                "Repository reload",
+                installation = payload.installation.id.into_inner()
+            );
+            let mut client = AutoGitHubClient::new(store.clone());
+            if let Err(error) = handle_installations_changed(&mut client, store, payload)
+                .instrument(span.clone())
+                .await
+            {
+                retry |= is_transient(&error);
+                span.log_error(error);
+            }
+        }
+        BorsEvent::MergeGroupChecksRequested(group) => {
+            let span = tracing::info_span!(
+                "Merge group checks requested",
+                repo = group.repository.to_string(),
+                branch = group.head_ref
+            );
+            if let Err(error) = handle_merge_group_checks_requested(store, group)
+                .instrument(span.clone())
+                .await
+            {
+                retry |= is_transient(&error);
+                span.log_error(error);
+            }
+        }
+        BorsEvent::MergeGroupDestroyed(payload) => {
+            let span = tracing::info_span!(
+                "Merge group destroyed",
+                repo = payload.group.repository.to_string(),
+                branch = payload.group.head_ref
+            );
+            let mut client = AutoGitHubClient::new(store.clone());
+            if let Err(error) = handle_merge_group_destroyed(&mut client, store, payload)
+                .instrument(span.clone())
+                .await
+            {
+                retry |= is_transient(&error);
+                span.log_error(error);
+            }
+        }
+        BorsEvent::BranchPushed(payload) => {
+            let span = tracing::info_span!(
+                "Branch pushed",
+                repo = payload.repository.to_string(),
+                branch = payload.branch
+            );
+            let mut client = AutoGitHubClient::new(store.clone());
+            if let Some(head) = payload.commits.last() {
+                if payload
+                    .changed_files
+                    .iter()
+                    .any(|file| file == CONFIG_FILE_PATH)
+                {
+                    if let Err(error) = validate_config(&mut client, &payload.repository, head)
+                        .instrument(span.clone())
+                        .await
+                    {
+                        retry |= is_transient(&error);
+                        span.log_error(error);
+                    }
+                }
+            }
+            if let Err(error) =
+                handle_base_branch_pushed(&mut client, store, &payload.repository, &payload.branch)
+                    .instrument(span.clone())
+                    .await
+            {
+                retry |= is_transient(&error);
+                span.log_error(error);
+            }
+            if let Err(error) = handle_branch_pushed(&mut client, store, payload)
+                .instrument(span.clone())
+                .await
+            {
+                retry |= is_transient(&error);
+                span.log_error(error);
+            }
+        }
+        BorsEvent::PullRequestUpdated(payload) => {
+            let span = tracing::info_span!(
+                "Pull request updated",
+                pr = format!("{}#{}", payload.repository, payload.number),
+            );
+            let mut client = AutoGitHubClient::new(store.clone());
+            if let Err(error) = handle_pull_request_updated(
+                &mut client,
+                &payload.repository,
+                payload.number,
+                &payload.head_sha,
+            )
+            .instrument(span.clone())
+            .await
+            {
+                retry |= is_transient(&error);
+                span.log_error(error);
+            }
+            if let Err(error) =
+                handle_pull_request_pushed(&mut client, store, &payload.repository, payload.number)
+                    .instrument(span.clone())
+                    .await
+            {
+                retry |= is_transient(&error);
                 span.log_error(error);
             }
         }
-        BorsEvent::InstallationsChanged => {
-            let span = tracing::info_span!("Repository reload");
-            todo!("Apper")
-            // although we might want to make sure that we have hook
+        BorsEvent::PullRequestClosed(payload) => {
+            let span = tracing::info_span!(
+                "Pull request closed",
+                pr = format!("{}#{}", payload.repository, payload.number),
+            );
+            if let Err(error) =
+                handle_pull_request_closed(store, &payload.repository, payload.number)
+                    .instrument(span.clone())
+                    .await
+            {
+                retry |= is_transient(&error);
+                span.log_error(error);
+            }
         }
         BorsEvent::WorkflowStarted(payload) => {
             /*if let Some((_, db)) = get_repo_state(state, &payload.repository) {
@@ -97,16 +279,90 @@ pub async fn handle_bors_event(event: BorsEvent) -> anyhow::Result<()> {
         }
         BorsEvent::Refresh => {
             let span = tracing::info_span!("Refresh");
-            // nop
+            let mut client = AutoGitHubClient::new(store.clone());
+            if let Err(error) = handle_refresh(&mut client, store)
+                .instrument(span.clone())
+                .await
+            {
+                retry |= is_transient(&error);
+                span.log_error(error);
+            }
         }
     }
+    if retry {
+        anyhow::bail!("Cannot handle the event because of a temporary error");
+    }
     Ok(())
 }
 
-async fn handle_comment(comment: PullRequestComment) -> anyhow::Result<()> {
+/// Reviewers whose latest review approves the pull request, given its reviews in
+/// chronological order. Plain comments (and pending reviews) do not change the state.
+fn approvers(reviews: Vec<Review>) -> BTreeSet<String> {
+    latest_approvers(
+        reviews
+            .into_iter()
+            .filter_map(|review| Some((review.user?.login, review.state?))),
+    )
+}
+
+fn latest_approvers(reviews: impl IntoIterator<Item = (String, ReviewState)>) -> BTreeSet<String> {
+    let mut latest = HashMap::new();
+    for (login, state) in reviews {
+        if !matches!(state, ReviewState::Commented | ReviewState::Pending) {
+            latest.insert(login, state);
+        }
+    }
+    latest
+        .into_iter()
+        .filter(|(_, state)| *state == ReviewState::Approved)
+        .map(|(login, _)| login)
+        .collect()
+}
+
+/// Decides whether an error might go away on its own (network problems, GitHub or KV outages,
+/// rate limits), so that handling the event again is worthwhile.
+fn is_transient(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if let Some(error) = cause.downcast_ref::<reqwest::Error>() {
+            !error.is_decode() && !error.is_builder()
+        } else if let Some(StatusError(status)) = cause.downcast_ref() {
+            status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+        } else if let Some(error) = cause.downcast_ref::<MergeError>() {
+            match error {
+                MergeError::Unknown { status, .. } => status.is_server_error(),
+                MergeError::NetworkError(_) => true,
+                _ => false,
+            }
+        } else if let Some(error) = cause.downcast_ref::<BranchUpdateError>() {
+            matches!(error, BranchUpdateError::RateLimited)
+        } else if let Some(error) = cause.downcast_ref::<AutoClientError>() {
+            matches!(error, AutoClientError::RateLimited(_))
+        } else {
+            cause.is::<StoreError>()
+        }
+    })
+}
+
+/// Checks if the comment was authored by this bot (or by any other bot),
+/// so that quoted commands in bot replies do not trigger the bot again.
+async fn is_comment_internal(client: &mut AutoGitHubClient, comment: &PullRequestComment) -> bool {
+    if comment.author.is_bot {
+        return true;
+    }
+    client
+        .logins()
+        .await
+        .iter()
+        .any(|login| login.eq_ignore_ascii_case(&comment.author.username))
+}
+
+async fn handle_comment(
+    client: &mut AutoGitHubClient,
+    store: &Store,
+    comment: PullRequestComment,
+) -> anyhow::Result<()> {
     let parser = CommandParser::new(CMD_PREFIX.get().unwrap());
     let commands = parser.parse_commands(&comment.text);
-    let mut client = AutoGitHubClient::new();
     let mut pr_data = PullRequestData {
         repository: comment.repository,
         author: comment.author,
@@ -123,17 +379,27 @@ async fn handle_comment(comment: PullRequestComment) -> anyhow::Result<()> {
                 let result = match command {
                     BorsCommand::Ping => {
                         let span = tracing::info_span!("Ping");
-                        command_ping(&mut client, &pr_data).instrument(span).await
+                        command_ping(client, &pr_data).instrument(span).await
+                    }
+                    BorsCommand::Config => {
+                        let span = tracing::info_span!("Config");
+                        command_config(client, &pr_data).instrument(span).await
                     }
                     BorsCommand::Try => {
                         let span = tracing::info_span!("Try");
-                        command_try_build(&mut client, &mut pr_data)
+                        command_try_build(client, store, &mut pr_data, false)
+                            .instrument(span)
+                            .await
+                    }
+                    BorsCommand::TrySquash => {
+                        let span = tracing::info_span!("Try squash");
+                        command_try_build(client, store, &mut pr_data, true)
                             .instrument(span)
                             .await
                     }
                     BorsCommand::TryCancel => {
                         let span = tracing::info_span!("Cancel try");
-                        command_try_cancel(&mut client, &mut pr_data)
+                        command_try_cancel(client, &mut pr_data)
                             .instrument(span)
                             .await
                     }
@@ -161,3 +427,68 @@ async fn handle_comment(comment: PullRequestComment) -> anyhow::Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+    use reqwest::StatusCode;
+
+    use super::{is_transient, latest_approvers};
+    use crate::config::ConfigError;
+    use crate::github::client::AutoClientError;
+    use crate::github::{BranchUpdateError, GithubRepo, MergeError, StatusError};
+    use crate::models::pulls::ReviewState;
+
+    #[test]
+    fn latest_review_counts() {
+        let review = |login: &str, state| (login.to_string(), state);
+        let approvers = latest_approvers([
+            review("bob", ReviewState::Approved),
+            review("alice", ReviewState::Approved),
+            review("bob", ReviewState::Commented),
+            review("carol", ReviewState::Approved),
+            review("carol", ReviewState::ChangesRequested),
+            review("dave", ReviewState::Approved),
+            review("dave", ReviewState::Dismissed),
+            review("alice", ReviewState::Approved),
+        ]);
+        assert_eq!(
+            approvers.into_iter().collect::<Vec<_>>(),
+            vec!["alice".to_string(), "bob".to_string()]
+        );
+    }
+
+    #[test]
+    fn transient_errors() {
+        let server = anyhow::Error::new(StatusError(StatusCode::BAD_GATEWAY))
+            .context("Could not get check runs");
+        assert!(is_transient(&server));
+        let limited = anyhow::Error::new(StatusError(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_transient(&limited));
+        let merge = anyhow::Error::new(MergeError::Unknown {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            text: String::new(),
+        });
+        assert!(is_transient(&merge));
+        let branch = anyhow::Error::new(BranchUpdateError::RateLimited);
+        assert!(is_transient(&branch));
+        let exhausted = anyhow::Error::new(AutoClientError::RateLimited(600));
+        assert!(is_transient(&exhausted));
+    }
+
+    #[test]
+    fn permanent_errors() {
+        let missing = anyhow::Error::new(StatusError(StatusCode::NOT_FOUND))
+            .context("Could not get pull request");
+        assert!(!is_transient(&missing));
+        let conflict = anyhow::Error::new(MergeError::Conflict);
+        assert!(!is_transient(&conflict));
+        let protected = anyhow::Error::new(BranchUpdateError::NotFastForward("main".to_owned()));
+        assert!(!is_transient(&protected));
+        let config: anyhow::Result<()> =
+            Err(ConfigError::Missing(GithubRepo::new("servo", "servo")).into());
+        assert!(!is_transient(
+            &config.context("Cannot load config").unwrap_err()
+        ));
+    }
+}
@@ -1,17 +1,22 @@
 use super::PullRequestData;
+use crate::config::Config;
 use crate::github::client::GitHubClient;
 
 pub(super) async fn command_ping<C: GitHubClient>(
     client: &mut C,
     pr_data: &PullRequestData,
 ) -> anyhow::Result<()> {
-    let text = if pr_data.repository.owner() == "servo" {
-        ":sleepy: I'm awake I'm awake"
-    } else {
-        "Pong 🏓!"
+    // Ping should answer quickly, even without a (valid) config
+    let messages = match Config::get_all_cached(client.cache(), &pr_data.repository).await {
+        Some(config) => config.messages,
+        None => {
+            tracing::debug!("No cached config, using default messages");
+            Default::default()
+        }
     };
+    let text = messages.ping(&pr_data.repository, &pr_data.author.username);
     client
-        .post_comment(&pr_data.repository, pr_data.number, text)
+        .post_comment(&pr_data.repository, pr_data.number, &text)
         .await?;
     Ok(())
 }
@@ -0,0 +1,80 @@
+use chrono::Duration;
+
+use crate::config::{Config, DEFAULT_TIMEOUT};
+use crate::github::client::GitHubClient;
+use crate::github::misc::{BuildStatus, PullRequestModel};
+use crate::github::{CommitSha, GithubRepo};
+use crate::store::{Store, PULL_REQUEST_PREFIX};
+use crate::utils::time::{format_duration, now};
+
+/// Cancels try builds that are pending for longer than the configured timeout.
+pub(super) async fn handle_refresh<C: GitHubClient>(
+    client: &mut C,
+    store: &Store,
+) -> anyhow::Result<()> {
+    for key in store.keys(PULL_REQUEST_PREFIX).await? {
+        let Some(mut pr) = store.get::<PullRequestModel>(&key).await? else {
+            continue;
+        };
+        if let Err(error) = check_try_build_timeout(client, store, &key, &mut pr).await {
+            tracing::warn!("Cannot check timeout of {key}: {error:?}");
+        }
+    }
+    Ok(())
+}
+
+async fn check_try_build_timeout<C: GitHubClient>(
+    client: &mut C,
+    store: &Store,
+    key: &str,
+    pr: &mut PullRequestModel,
+) -> anyhow::Result<()> {
+    let Some(build) = pr.try_build.as_mut() else {
+        return Ok(());
+    };
+    if build.status != BuildStatus::Pending {
+        return Ok(());
+    }
+    let repo: GithubRepo = pr.repository.parse()?;
+    let config = Config::get_all(client, &repo).await.ok();
+    let timeout = config.as_ref().map_or(DEFAULT_TIMEOUT, Config::timeout);
+    let timeout = Duration::seconds(timeout as i64);
+    if now() - build.created_at < timeout {
+        return Ok(());
+    }
+
+    let sha = CommitSha(build.commit_sha.clone());
+    let runs = client.get_workflow_runs(&repo, &sha).await?;
+    let pending: Vec<_> = runs
+        .iter()
+        .filter(|run| run.status != "completed")
+        .map(|run| run.id)
+        .collect();
+    if !runs.is_empty() && pending.is_empty() {
+        // The build has finished, only its result was not recorded
+        build.status = if runs
+            .iter()
+            .all(|run| run.conclusion.as_deref() == Some("success"))
+        {
+            BuildStatus::Success
+        } else {
+            BuildStatus::Failure
+        };
+        return store.put(key, pr).await;
+    }
+
+    tracing::info!("Try build {sha} of {repo}#{} timed out", pr.number);
+    // The PR is told first: once the workflows are cancelled, the next refresh
+    // would take the build for a finished one
+    let messages = config.map(|config| config.messages).unwrap_or_default();
+    client
+        .post_comment(
+            &repo,
+            pr.number,
+            &messages.try_timed_out(pr.number, &format_duration(timeout)),
+        )
+        .await?;
+    client.cancel_workflows(&repo, pending).await?;
+    build.status = BuildStatus::Timeouted;
+    store.put(key, pr).await
+}
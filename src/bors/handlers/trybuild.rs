@@ -1,6 +1,6 @@
 use anyhow::anyhow;
 
-use super::PullRequestData;
+use super::{check_permissions, PullRequestData};
 use crate::bors::event::{PullRequestComment, PR};
 use crate::config::Config;
 use crate::github::client::GitHubClient;
@@ -8,38 +8,88 @@ use crate::github::misc::{
     BuildModel, BuildStatus, PullRequestModel, WorkflowStatus, WorkflowType,
 };
 use crate::github::{
-    GithubRepo, GithubUser, LabelTrigger, MergeError, PullRequest, PullRequestNumber,
+    BranchUpdateError, CommitSha, GithubRepo, LabelTrigger, MergeError, PullRequest,
+    PullRequestNumber,
 };
-use crate::permissions::{PermissionResolver, PermissionType};
-
-// This branch serves for preparing the final commit.
-// It will be reset to master and merged with the branch that should be tested.
-// Because this action (reset + merge) is not atomic, this branch should not run CI checks to avoid
-// starting them twice.
-const TRY_MERGE_BRANCH_NAME: &str = "automation/bors/try-merge";
-
-// This branch should run CI checks.
-pub(super) const TRY_BRANCH_NAME: &str = "try";
+use crate::messages::Messages;
+use crate::models::repos::RepoCommit;
+use crate::permissions::PermissionType;
+use crate::store::{pull_request_key, Store};
+use crate::utils::time::now;
 
 /// Performs a so-called try build - merges the PR branch into a special branch designed
 /// for running CI checks.
+///
+/// With `squash`, the PR commits are squashed into a single commit which is merged instead,
+/// the PR branch is left untouched.
 pub(super) async fn command_try_build<C: GitHubClient>(
     client: &mut C,
+    store: &Store,
     pr_data: &mut PullRequestData,
+    squash: bool,
 ) -> anyhow::Result<()> {
-    let config = Config::get_all(&pr_data.repository).await.unwrap();
-    if !check_try_permissions(
-        client,
-        &pr_data.repository,
-        &pr_data.author,
-        &config,
-        pr_data.number,
-    )
-    .await?
-    {
+    let config = Config::get_all(client, &pr_data.repository).await?;
+    if !check_permissions(client, pr_data, &config, PermissionType::Try).await? {
         return Ok(());
     }
-    let pr = pr_data.pr.get_pull(client).await;
+    let pr = pr_data.pr.get_pull(client).await.clone();
+    let try_branch = config.try_branch();
+    let try_merge_branch = config.try_merge_branch();
+
+    // A retried event (or a repeated command) must not start a second build of the same head
+    let key = pull_request_key(&pr_data.repository, pr.number);
+    let mut model = store.get::<PullRequestModel>(&key).await?;
+    let pending = model
+        .as_ref()
+        .and_then(|model| model.try_build.as_ref())
+        .filter(|build| {
+            build.status == BuildStatus::Pending
+                && build.head_sha.as_deref() == Some(pr.head.sha.as_ref())
+        });
+    if let Some(build) = pending {
+        if build.announced {
+            tracing::warn!("Try build of {} already in progress", pr.head.sha);
+            client
+                .post_comment(
+                    &pr_data.repository,
+                    pr.number,
+                    &config
+                        .messages
+                        .try_in_progress(pr.number, pr.head.sha.as_ref()),
+                )
+                .await?;
+            return Ok(());
+        }
+        tracing::info!("Resuming try build of {}", pr.head.sha);
+        let merge_sha = CommitSha(build.commit_sha.clone());
+        let model = model.as_mut().expect("pending build without a model");
+        return start_try_build(
+            client,
+            store,
+            &config,
+            &pr_data.repository,
+            &pr,
+            model,
+            merge_sha,
+        )
+        .await;
+    }
+
+    let commits = if squash || config.wants_co_authors() {
+        client
+            .get_pull_request_commits(&pr_data.repository, pr.number)
+            .await?
+    } else {
+        Vec::new()
+    };
+    let message = config.commit_message(&pr, "<try>", &co_authors(&commits));
+    let head = if squash {
+        let sha = squash_commits(client, &pr_data.repository, &pr, &commits).await?;
+        tracing::debug!("Squashed {} commits into {sha}", commits.len());
+        sha
+    } else {
+        pr.head.sha.clone()
+    };
 
     /*if let Some(ref build) = pr_model.try_build {
         if build.status == BuildStatus::Pending {
@@ -57,43 +107,57 @@ pub(super) async fn command_try_build<C: GitHubClient>(
     //let mut pull = comment.pr.clone();
 
     // main branch on try merge branch
-    client
-        .set_branch_to_sha(&pr_data.repository, TRY_MERGE_BRANCH_NAME, &pr.base.sha)
+    if let Err(error) = client
+        .set_branch_to_sha(&pr_data.repository, try_merge_branch, &pr.base.sha)
         .await
-        .map_err(|error| anyhow!("Cannot set try merge branch to main branch: {error:?}"))?;
-    // do a merge
-    match client
-        .merge_branches(
+    {
+        return report_branch_update_error(
+            client,
+            &config.messages,
             &pr_data.repository,
-            TRY_MERGE_BRANCH_NAME,
-            &pr.head.sha,
-            &auto_merge_commit_message(pr, "<try>"),
+            pr.number,
+            error,
         )
         .await
+        .map_err(|error| anyhow!("Cannot set try merge branch to main branch: {error:?}"));
+    }
+    // do a merge
+    match client
+        .merge_branches(&pr_data.repository, try_merge_branch, &head, &message)
+        .await
     {
         Ok(merge_sha) => {
             tracing::debug!("Merge successful, SHA: {merge_sha}");
-            // push to ci
-            client
-                .set_branch_to_sha(&pr_data.repository, TRY_BRANCH_NAME, &merge_sha)
-                .await
-                .map_err(|error| anyhow!("Cannot set try branch to main branch: {error:?}"))?;
-
-            tracing::info!("Try build started");
-
-            //handle_label_trigger(repo, pr.number, LabelTrigger::TryBuildStarted).await?;
 
-            client
-                .post_comment(
-                    &pr_data.repository,
-                    pr.number,
-                    &format!(
-                        ":hourglass: Trying commit {} with merge {merge_sha}…",
-                        pr.head.sha
-                    ),
-                )
-                .await?;
-            Ok(())
+            // Remember the build before pushing it, so that a retry resumes it
+            // instead of merging again
+            let model = model.get_or_insert_with(|| PullRequestModel {
+                repository: pr_data.repository.to_string(),
+                number: pr.number,
+                try_build: None,
+                created_at: now(),
+            });
+            model.try_build = Some(BuildModel {
+                repository: pr_data.repository.to_string(),
+                branch: try_branch.to_string(),
+                commit_sha: merge_sha.to_string(),
+                status: BuildStatus::Pending,
+                created_at: now(),
+                head_sha: Some(pr.head.sha.to_string()),
+                announced: false,
+            });
+            store.put(&key, model).await?;
+
+            start_try_build(
+                client,
+                store,
+                &config,
+                &pr_data.repository,
+                &pr,
+                model,
+                merge_sha,
+            )
+            .await
         }
         Err(error) => match error.downcast_ref() {
             Some(MergeError::Conflict) => {
@@ -102,7 +166,9 @@ pub(super) async fn command_try_build<C: GitHubClient>(
                     .post_comment(
                         &pr_data.repository,
                         pr.number,
-                        &merge_conflict_message(&pr.head.name),
+                        &config
+                            .messages
+                            .merge_conflict(pr.number, &pr.head.name, &pr.base.name),
                     )
                     .await?;
                 Ok(())
@@ -112,20 +178,59 @@ pub(super) async fn command_try_build<C: GitHubClient>(
     }
 }
 
+/// Pushes the merge commit of a stored pending build to the try branch and announces it.
+///
+/// Every step can be repeated, a failed push marks the build as failed.
+async fn start_try_build<C: GitHubClient>(
+    client: &mut C,
+    store: &Store,
+    config: &Config,
+    repo: &GithubRepo,
+    pr: &PullRequest,
+    model: &mut PullRequestModel,
+    merge_sha: CommitSha,
+) -> anyhow::Result<()> {
+    let key = pull_request_key(repo, pr.number);
+    // push to ci
+    if let Err(error) = client
+        .set_branch_to_sha(repo, config.try_branch(), &merge_sha)
+        .await
+    {
+        if let Some(build) = model.try_build.as_mut() {
+            build.status = BuildStatus::Failure;
+        }
+        store.put(&key, model).await?;
+        return report_branch_update_error(client, &config.messages, repo, pr.number, error)
+            .await
+            .map_err(|error| anyhow!("Cannot set try branch to main branch: {error:?}"));
+    }
+
+    tracing::info!("Try build started");
+
+    //handle_label_trigger(repo, pr.number, LabelTrigger::TryBuildStarted).await?;
+
+    client
+        .post_comment(
+            repo,
+            pr.number,
+            &config
+                .messages
+                .try_started(pr.number, pr.head.sha.as_ref(), merge_sha.as_ref()),
+        )
+        .await?;
+    if let Some(build) = model.try_build.as_mut() {
+        build.announced = true;
+    }
+    store.put(&key, model).await?;
+    Ok(())
+}
+
 pub(super) async fn command_try_cancel<C: GitHubClient>(
     client: &mut C,
     comment: &mut PullRequestData,
 ) -> anyhow::Result<()> {
-    let config = Config::get_all(&comment.repository).await.unwrap();
-    if !check_try_permissions(
-        client,
-        &comment.repository,
-        &comment.author,
-        &config,
-        comment.number,
-    )
-    .await?
-    {
+    let config = Config::get_all(client, &comment.repository).await?;
+    if !check_permissions(client, comment, &config, PermissionType::Try).await? {
         return Ok(());
     }
 
@@ -181,78 +286,167 @@ fn get_pending_build(pr: PullRequestModel) -> Option<BuildModel> {
         .and_then(|b| (b.status == BuildStatus::Pending).then_some(b))
 }*/
 
-fn auto_merge_commit_message(pr: &PullRequest, reviewer: &str) -> String {
-    let pr_number = pr.number;
-    format!(
-        r#"Auto merge of #{pr_number} - {pr_label}, r={reviewer}
-{pr_title}
-
-{pr_message}"#,
-        pr_label = pr.head_label,
-        pr_title = pr.title,
-        pr_message = pr.message
-    )
+/// Creates a single commit with the tree of the PR head on top of the merge base,
+/// the PR branch is not updated.
+async fn squash_commits<C: GitHubClient>(
+    client: &mut C,
+    repo: &GithubRepo,
+    pr: &PullRequest,
+    commits: &[RepoCommit],
+) -> anyhow::Result<CommitSha> {
+    let base = client
+        .get_merge_base(repo, &pr.base.sha, &pr.head.sha)
+        .await?;
+    let tree = client.get_commit_tree(repo, &pr.head.sha).await?;
+    let author = commits
+        .iter()
+        .find_map(|commit| commit.commit.author.as_ref())
+        .map(|author| &author.user);
+    client
+        .create_commit(
+            repo,
+            &squash_commit_message(pr, commits),
+            &tree,
+            &[base],
+            author,
+        )
+        .await
 }
 
-fn merge_conflict_message(branch: &str) -> String {
-    format!(
-        r#":lock: Merge conflict
-
-This pull request and the master branch diverged in a way that cannot
- be automatically merged. Please rebase on top of the latest master
- branch, and let the reviewer approve again.
-
-<details><summary>How do I rebase?</summary>
-
-Assuming `self` is your fork and `upstream` is this repository,
- you can resolve the conflict following these steps:
-
-1. `git checkout {branch}` *(switch to your branch)*
-2. `git fetch upstream master` *(retrieve the latest master)*
-3. `git rebase upstream/master -p` *(rebase on top of it)*
-4. Follow the on-screen instruction to resolve conflicts (check `git status` if you got lost).
-5. `git push self {branch} --force-with-lease` *(update this PR)*
-
-You may also read
- [*Git Rebasing to Resolve Conflicts* by Drew Blessing](http://blessing.io/git/git-rebase/open-source/2015/08/23/git-rebasing-to-resolve-conflicts.html)
- for a short tutorial.
-
-Please avoid the ["**Resolve conflicts**" button](https://help.github.com/articles/resolving-a-merge-conflict-on-github/) on GitHub.
- It uses `git merge` instead of `git rebase` which makes the PR commit history more difficult to read.
-
-Sometimes step 4 will complete without asking for resolution. This is usually due to difference between how `Cargo.lock` conflict is
-handled during merge and rebase. This is normal, and you should still perform step 5 to update this PR.
+/// Title and description of the PR followed by the titles of the squashed commits
+/// and `Co-authored-by` trailers of their authors (except the first one, who is the author
+/// of the squashed commit).
+fn squash_commit_message(pr: &PullRequest, commits: &[RepoCommit]) -> String {
+    let mut message = format!("{} (#{})\n\n", pr.title, pr.number);
+    if !pr.message.trim().is_empty() {
+        message += &format!("{}\n\n", pr.message.trim_end());
+    }
+    for commit in commits {
+        let title = commit.commit.message.lines().next().unwrap_or_default();
+        message += &format!("* {title}\n");
+    }
+    let co_authors = co_authors(commits);
+    if co_authors.len() > 1 {
+        message += "\n";
+        for author in &co_authors[1..] {
+            message += &format!("Co-authored-by: {author}\n");
+        }
+    }
+    message.trim_end().to_owned()
+}
 
-</details>  
-"#
-    )
+/// Distinct `Name <email>` of the commit authors, in the order of the commits.
+fn co_authors(commits: &[RepoCommit]) -> Vec<String> {
+    let mut authors = Vec::new();
+    for author in commits
+        .iter()
+        .filter_map(|commit| commit.commit.author.as_ref())
+    {
+        let author = format!("{} <{}>", author.user.name, author.user.email);
+        if !authors.contains(&author) {
+            authors.push(author);
+        }
+    }
+    authors
 }
 
-async fn check_try_permissions<C: GitHubClient>(
+/// Explains on the PR why a branch could not be updated.
+///
+/// Errors that are not understood are returned after the comment is posted.
+async fn report_branch_update_error<C: GitHubClient>(
     client: &mut C,
+    messages: &Messages,
     repo: &GithubRepo,
-    author: &GithubUser,
-    config: &Config,
-    pr_number: PullRequestNumber,
-) -> anyhow::Result<bool> {
-    let result = if !config
-        .has_permission(&author.username, PermissionType::Try)
-        .await
-    {
-        tracing::info!("Permission denied");
-        client
-            .post_comment(
-                repo,
-                pr_number,
-                &format!(
-                    "@{}: :key: Insufficient privileges: not in try users",
-                    author.username
-                ),
-            )
-            .await?;
-        false
+    pr: PullRequestNumber,
+    error: anyhow::Error,
+) -> anyhow::Result<()> {
+    tracing::warn!("Cannot update branch: {error:?}");
+    let known = error
+        .downcast_ref::<BranchUpdateError>()
+        .filter(|error| !matches!(error, BranchUpdateError::Custom(_)));
+    let message = messages.branch_update_failed(pr, &branch_update_reason(known));
+    let understood = known.is_some();
+    client.post_comment(repo, pr, &message).await?;
+    if understood {
+        Ok(())
     } else {
-        true
-    };
-    Ok(result)
+        Err(error)
+    }
+}
+
+fn branch_update_reason(error: Option<&BranchUpdateError>) -> String {
+    match error {
+        Some(BranchUpdateError::BranchNotFound(branch)) => {
+            format!("branch `{branch}` does not exist and cannot be created.")
+        }
+        Some(BranchUpdateError::Protected { branch, reason }) => format!(
+            "branch `{branch}` is protected ({reason}).\n\nPlease exclude it from branch protection rules, so that bors can push to it."
+        ),
+        Some(BranchUpdateError::Forbidden { branch, reason }) => format!(
+            "bors is not allowed to push to branch `{branch}` ({reason}).\n\nPlease check that it has write access to the repository contents."
+        ),
+        Some(BranchUpdateError::NotFastForward(branch)) => {
+            format!("branch `{branch}` was updated by someone else in the meantime.")
+        }
+        Some(BranchUpdateError::RateLimited) => {
+            "GitHub API rate limit was exceeded, please try again later.".to_string()
+        }
+        Some(BranchUpdateError::Custom(_)) | None => "an unexpected error occurred.".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bors::handlers::trybuild::squash_commit_message;
+    use crate::github::{Branch, CommitSha, PullRequest};
+    use crate::models::repos::RepoCommit;
+
+    fn commit(message: &str, author: &str) -> RepoCommit {
+        serde_json::from_value(serde_json::json!({
+            "url": "https://api.github.com/repos/owner/name/commits/abc",
+            "sha": "abc",
+            "node_id": "",
+            "html_url": "",
+            "comments_url": "",
+            "commit": {
+                "url": "https://api.github.com/repos/owner/name/git/commits/abc",
+                "author": {"name": author, "email": format!("{author}@example.com")},
+                "committer": null,
+                "message": message,
+                "comment_count": 0,
+                "tree": {"sha": "def", "url": "https://api.github.com/repos/owner/name/git/trees/def"},
+            },
+            "author": null,
+            "committer": null,
+            "parents": [],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn squash_message() {
+        let branch = |name: &str| Branch {
+            name: name.to_string(),
+            sha: CommitSha("abc".to_string()),
+        };
+        let pr = PullRequest {
+            number: 5,
+            head_label: "alice:feature".to_string(),
+            head: branch("feature"),
+            base: branch("main"),
+            title: "Add feature".to_string(),
+            message: "Description\n".to_string(),
+            open: true,
+            mergeable: Some(true),
+        };
+        let commits = [
+            commit("Add feature\n\nDetails", "alice"),
+            commit("fixup", "bob"),
+            commit("fixup", "alice"),
+        ];
+        assert_eq!(
+            squash_commit_message(&pr, &commits),
+            "Add feature (#5)\n\nDescription\n\n* Add feature\n* fixup\n* fixup\n\nCo-authored-by: bob <bob@example.com>"
+        );
+    }
 }
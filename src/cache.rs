@@ -0,0 +1,189 @@
+//! Caches data that is expensive to fetch from GitHub across webhook requests.
+//!
+//! Cache failures are only logged, every lookup falls back to the GitHub API.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::github::{CommitSha, GithubRepo};
+use crate::models::{InstallationId, InstallationToken};
+use crate::permissions::RepoPermission;
+use crate::store::{installed_repository_key, InstalledRepository, Store};
+
+/// Config of a commit never changes, so it can be kept for long.
+const CONFIG_TTL: u64 = 7 * 24 * 60 * 60;
+/// Login of bot accounts is unlikely to change.
+const LOGIN_TTL: u64 = 24 * 60 * 60;
+/// Default branch is rarely renamed.
+const DEFAULT_BRANCH_TTL: u64 = 60 * 60;
+/// Installations of organizations change only when the app is (re)installed.
+const ORG_INSTALLATION_TTL: u64 = 24 * 60 * 60;
+/// Permissions may be revoked, so they are kept only briefly.
+const PERMISSION_TTL: u64 = 10 * 60;
+
+#[derive(Clone, Default)]
+pub struct Cache(Store);
+
+impl Cache {
+    pub fn new(store: Store) -> Self {
+        Self(store)
+    }
+
+    async fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        match self.0.get(key).await {
+            Ok(value) => value,
+            Err(error) => {
+                tracing::warn!("Cache lookup of {key} failed: {error:?}");
+                None
+            }
+        }
+    }
+
+    async fn put<T: Serialize>(&self, key: &str, value: &T, ttl: u64) {
+        if let Err(error) = self.0.put_with_ttl(key, value, ttl).await {
+            tracing::warn!("Cannot cache {key}: {error:?}");
+        }
+    }
+
+    pub async fn installation(&self, repo: &GithubRepo) -> Option<InstallationId> {
+        self.get::<InstalledRepository>(&installed_repository_key(repo))
+            .await
+            .map(|repo| repo.installation)
+    }
+
+    pub async fn set_installation(&self, repo: &GithubRepo, installation: InstallationId) {
+        if let Err(error) = self
+            .0
+            .put(
+                &installed_repository_key(repo),
+                &InstalledRepository { installation },
+            )
+            .await
+        {
+            tracing::warn!("Cannot cache installation of {repo}: {error:?}");
+        }
+    }
+
+    /// Installation of the app on the organization.
+    pub async fn org_installation(&self, org: &str) -> Option<InstallationId> {
+        self.get(&format!("org-installation:{org}")).await
+    }
+
+    pub async fn set_org_installation(&self, org: &str, installation: InstallationId) {
+        self.put(
+            &format!("org-installation:{org}"),
+            &installation,
+            ORG_INSTALLATION_TTL,
+        )
+        .await
+    }
+
+    pub async fn installation_token(
+        &self,
+        installation: InstallationId,
+    ) -> Option<InstallationToken> {
+        self.get(&format!("token:{installation}")).await
+    }
+
+    /// Caches the token for `ttl` seconds, it should end before the token expires.
+    pub async fn set_installation_token(
+        &self,
+        installation: InstallationId,
+        token: &InstallationToken,
+        ttl: u64,
+    ) {
+        self.put(&format!("token:{installation}"), token, ttl).await
+    }
+
+    /// Config file `path` of the repository at the given commit.
+    ///
+    /// Returns `Some(None)` if it is known that the commit has no config.
+    pub async fn config(
+        &self,
+        repo: &GithubRepo,
+        path: &str,
+        sha: &CommitSha,
+    ) -> Option<Option<Config>> {
+        self.get(&format!("config:{repo}:{sha}:{path}")).await
+    }
+
+    pub async fn set_config(
+        &self,
+        repo: &GithubRepo,
+        path: &str,
+        sha: &CommitSha,
+        config: Option<&Config>,
+    ) {
+        self.put(&format!("config:{repo}:{sha}:{path}"), &config, CONFIG_TTL)
+            .await
+    }
+
+    /// Last loaded config file `path` of the repository, whatever its commit.
+    pub async fn latest_config(&self, repo: &GithubRepo, path: &str) -> Option<Config> {
+        self.get(&format!("latest-config:{repo}:{path}")).await
+    }
+
+    pub async fn set_latest_config(&self, repo: &GithubRepo, path: &str, config: &Config) {
+        self.put(&format!("latest-config:{repo}:{path}"), config, CONFIG_TTL)
+            .await
+    }
+
+    pub async fn default_branch(&self, repo: &GithubRepo) -> Option<String> {
+        self.get(&format!("default-branch:{repo}")).await
+    }
+
+    pub async fn set_default_branch(&self, repo: &GithubRepo, branch: &str) {
+        self.put(
+            &format!("default-branch:{repo}"),
+            &branch,
+            DEFAULT_BRANCH_TTL,
+        )
+        .await
+    }
+
+    /// Login of the bot account of the given kind (`pat` or `app`).
+    pub async fn login(&self, account: &str) -> Option<String> {
+        self.get(&format!("login:{account}")).await
+    }
+
+    pub async fn set_login(&self, account: &str, login: &str) {
+        self.put(&format!("login:{account}"), &login, LOGIN_TTL)
+            .await
+    }
+
+    /// Whether the user is a member of the team (`org/team`).
+    pub async fn team_membership(&self, team: &str, user: &str) -> Option<bool> {
+        self.get(&format!("team:{team}:{user}")).await
+    }
+
+    pub async fn set_team_membership(&self, team: &str, user: &str, member: bool) {
+        self.put(&format!("team:{team}:{user}"), &member, PERMISSION_TTL)
+            .await
+    }
+
+    /// Permission of the user on the repository.
+    ///
+    /// Returns `Some(None)` if it is known that the user is not a collaborator.
+    pub async fn repo_permission(
+        &self,
+        repo: &GithubRepo,
+        user: &str,
+    ) -> Option<Option<RepoPermission>> {
+        self.get(&format!("permission:{repo}:{user}")).await
+    }
+
+    pub async fn set_repo_permission(
+        &self,
+        repo: &GithubRepo,
+        user: &str,
+        permission: Option<RepoPermission>,
+    ) {
+        self.put(
+            &format!("permission:{repo}:{user}"),
+            &permission,
+            PERMISSION_TTL,
+        )
+        .await
+    }
+}
@@ -1,11 +1,17 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 use std::sync::OnceLock;
 
-use reqwest::Client;
+use anyhow::Context;
 use serde::de::Error;
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-use crate::github::{GithubRepo, LabelModification, LabelTrigger};
+use crate::cache::Cache;
+use crate::github::client::GitHubClient;
+use crate::github::{CommitSha, GithubRepo, LabelModification, LabelTrigger, PullRequest};
+use crate::messages::{render, Messages, MESSAGE_KEYS};
+use crate::permissions::RepoPermission;
 
 /// Prefix for bot (default `@bors-servo`)
 pub static CMD_PREFIX: OnceLock<String> = OnceLock::new();
@@ -17,21 +23,103 @@ pub static PAT: OnceLock<String> = OnceLock::new();
 pub static APP_ID: OnceLock<String> = OnceLock::new();
 /// Private key used to authenticate as a Github App.
 pub static PRIVATE_KEY: OnceLock<String> = OnceLock::new();
+/// Organization config inherited by repositories without `inherit`.
+pub static ORG_CONFIG_REPO: OnceLock<ConfigSource> = OnceLock::new();
+
+/// Time (in seconds) after which a try build is cancelled, unless configured
+pub const DEFAULT_TIMEOUT: u64 = 4 * 60 * 60;
+
+// This branch serves for preparing the final commit.
+// It will be reset to master and merged with the branch that should be tested.
+// Because this action (reset + merge) is not atomic, this branch should not run CI checks to avoid
+// starting them twice.
+pub const DEFAULT_TRY_MERGE_BRANCH: &str = "automation/bors/try-merge";
+
+// This branch should run CI checks.
+pub const DEFAULT_TRY_BRANCH: &str = "try";
+
+/// Message of the merge commits, unless configured
+pub const DEFAULT_COMMIT_MESSAGE: &str =
+    "Auto merge of #{pr} - {label}, r={reviewer}\n{title}\n\n{body}";
 
 /// Config file to search in repo
-const CONFIG_FILE_PATH: &str = "bors-mq.toml";
-/// Organistaions global config repo
-#[cfg(feature = "servo")]
-pub const ORG_CONFIG_REPO: &str = "saltfs";
+pub const CONFIG_FILE_PATH: &str = "bors-mq.toml";
+/// Top-level keys of [`Config`], others are reported by [`Config::validate`]
+const KNOWN_KEYS: &[&str] = &[
+    "labels",
+    "reviewers",
+    "try_users",
+    "try_choosers",
+    "fork_try",
+    "timeout",
+    "reviewer_teams",
+    "try_teams",
+    "min_repo_permission",
+    "inherit",
+    "messages",
+    "try_branch",
+    "try_merge_branch",
+    "commit_message",
+];
+
+/// Location of a config file in `owner/repo[:path]` format,
+/// the path defaults to [`CONFIG_FILE_PATH`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigSource {
+    pub repo: GithubRepo,
+    pub path: String,
+}
+
+impl FromStr for ConfigSource {
+    type Err = anyhow::Error;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let (repo, path) = match source.split_once(':') {
+            Some((repo, path)) => (repo, path.trim_start_matches('/')),
+            None => (source, CONFIG_FILE_PATH),
+        };
+        if path.is_empty() {
+            return Err(anyhow::anyhow!("Missing path of the config in {source}"));
+        }
+        Ok(Self {
+            repo: repo.parse()?,
+            path: path.to_owned(),
+        })
+    }
+}
+
+impl Display for ConfigSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.repo, self.path)
+    }
+}
+
+impl Serialize for ConfigSource {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ConfigSource {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(|error| D::Error::custom(format!("{error}")))
+    }
+}
 
 /// Configuration of a repository loaded from a `bors.toml`
 /// file located in the root of the repository file tree.
-#[derive(serde::Deserialize, Debug)]
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
 pub struct Config {
-    /// Currently unimplemented
+    /// Labels added or removed on the pull request when something happens
     ///
-    /// Inheritance: Merged
-    #[serde(default, deserialize_with = "deserialize_labels")]
+    /// Inheritance: Merged (per trigger)
+    #[serde(
+        default,
+        deserialize_with = "deserialize_labels",
+        serialize_with = "serialize_labels"
+    )]
     pub labels: HashMap<LabelTrigger, Vec<LabelModification>>,
     /// List of reviewers
     ///
@@ -52,76 +140,556 @@ pub struct Config {
     ///
     /// Inheritance: Override
     #[serde(default)]
-    pub fork_try: bool,
+    pub fork_try: Option<bool>,
+    /// Time (in seconds) after which a pending try build is cancelled
+    ///
+    /// Inheritance: Override
+    #[serde(default)]
+    pub timeout: Option<u64>,
+    /// Teams (`org/team`) whose members are reviewers
+    ///
+    /// Inheritance: Merged
+    #[serde(default)]
+    pub reviewer_teams: HashSet<String>,
+    /// Teams (`org/team`) whose members are try users
+    ///
+    /// Inheritance: Merged
+    #[serde(default)]
+    pub try_teams: HashSet<String>,
+    /// Collaborators with at least this permission on the repository are reviewers
+    ///
+    /// Inheritance: Override
+    #[serde(default)]
+    pub min_repo_permission: Option<RepoPermission>,
+    /// Organization config (`owner/repo[:path]`) this config is merged into,
+    /// defaults to `ORG_CONFIG_REPO` variable
+    ///
+    /// Inheritance: None (only read from the repository config)
+    #[serde(default)]
+    pub inherit: Option<ConfigSource>,
+    /// Templates of messages posted by the bot
+    ///
+    /// Inheritance: Override (per message)
+    #[serde(default)]
+    pub messages: Messages,
+    /// Branch running CI checks of try builds
+    ///
+    /// Inheritance: Override
+    #[serde(default)]
+    pub try_branch: Option<String>,
+    /// Branch where the PR is merged before it is pushed to `try_branch`
+    ///
+    /// Inheritance: Override
+    #[serde(default)]
+    pub try_merge_branch: Option<String>,
+    /// Template of the merge commit message, placeholders: `{pr}`, `{label}` (of the PR head),
+    /// `{reviewer}`, `{title}`, `{body}`, `{co_authored_by}` (trailers of the PR commit authors)
+    ///
+    /// Inheritance: Override
+    #[serde(default)]
+    pub commit_message: Option<String>,
+}
+
+/// Why the config of a repository cannot be loaded.
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("`{CONFIG_FILE_PATH}` is missing in {0}")]
+    Missing(GithubRepo),
+    #[error("`{CONFIG_FILE_PATH}` of {repo} is invalid: {error}")]
+    Invalid {
+        repo: GithubRepo,
+        error: toml::de::Error,
+    },
+    #[error("Cannot fetch `{CONFIG_FILE_PATH}`: {0:?}")]
+    Network(#[from] anyhow::Error),
+}
+
+/// Problem found in a config file.
+#[derive(Debug, PartialEq)]
+pub struct ConfigDiagnostic {
+    /// Line of the problem (starting from 1), if known
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+/// Result of a strict validation of a config file.
+#[derive(Debug, Default)]
+pub struct ConfigValidation {
+    /// The config cannot be loaded
+    pub errors: Vec<ConfigDiagnostic>,
+    /// The config is loaded, but some parts are ignored
+    pub warnings: Vec<ConfigDiagnostic>,
+}
+
+/// Line (starting from 1) of the byte `offset`.
+fn line_of_offset(text: &str, offset: usize) -> usize {
+    text[..offset.min(text.len())].matches('\n').count() + 1
+}
+
+/// Line (starting from 1) defining the top-level `key` or its table.
+fn line_of_key(text: &str, key: &str) -> Option<usize> {
+    text.lines()
+        .position(|line| {
+            let line = line.trim();
+            line == format!("[{key}]")
+                || line
+                    .strip_prefix(key)
+                    .is_some_and(|rest| rest.trim_start().starts_with('='))
+        })
+        .map(|index| index + 1)
 }
 
 impl Config {
+    /// Strictly parses the config, reporting where it is invalid
+    /// and which keys are unknown (they would be silently ignored).
+    ///
+    /// The try branches are force-pushed by bors, so they must differ from each other
+    /// and from the `default_branch` of the repository.
+    pub fn validate(text: &str, default_branch: &str) -> ConfigValidation {
+        let mut validation = ConfigValidation::default();
+        let deserializer = toml::Deserializer::new(text);
+        let config = match serde_path_to_error::deserialize::<_, Config>(deserializer) {
+            Ok(config) => config,
+            Err(error) => {
+                let path = error.path().to_string();
+                let error = error.into_inner();
+                let message = error.message().trim_end();
+                validation.errors.push(ConfigDiagnostic {
+                    line: error.span().map(|span| line_of_offset(text, span.start)),
+                    message: if path == "." {
+                        message.to_owned()
+                    } else {
+                        format!("`{path}`: {message}")
+                    },
+                });
+                return validation;
+            }
+        };
+        if config.try_branch() == config.try_merge_branch() {
+            validation.errors.push(ConfigDiagnostic {
+                line: line_of_key(text, "try_branch")
+                    .or_else(|| line_of_key(text, "try_merge_branch")),
+                message: "`try_branch` and `try_merge_branch` must be different branches"
+                    .to_owned(),
+            });
+        }
+        for (key, branch) in [
+            ("try_branch", config.try_branch()),
+            ("try_merge_branch", config.try_merge_branch()),
+        ] {
+            if branch == default_branch {
+                validation.errors.push(ConfigDiagnostic {
+                    line: line_of_key(text, key),
+                    message: format!(
+                        "`{key}` must not be the default branch `{default_branch}`, bors overwrites it"
+                    ),
+                });
+            }
+        }
+        if let Ok(table) = toml::from_str::<toml::Table>(text) {
+            for key in table
+                .keys()
+                .filter(|key| !KNOWN_KEYS.contains(&key.as_str()))
+            {
+                validation.warnings.push(ConfigDiagnostic {
+                    line: line_of_key(text, key),
+                    message: format!("Unknown key `{key}` is ignored"),
+                });
+            }
+            // Messages are optional one by one, so a typo would silently use the default
+            if let Some(toml::Value::Table(messages)) = table.get("messages") {
+                for key in messages
+                    .keys()
+                    .filter(|key| !MESSAGE_KEYS.contains(&key.as_str()))
+                {
+                    validation.warnings.push(ConfigDiagnostic {
+                        line: line_of_key(text, key),
+                        message: format!("Unknown key `messages.{key}` is ignored"),
+                    });
+                }
+            }
+        }
+        validation
+    }
+
     /// Merges two config
     ///
     /// global is org config, local config is repos config
     fn merge(mut global: Self, local: Self) -> Self {
-        // this field is partiali merged
-        global.labels.extend(local.labels.into_iter());
+        // this field is merged per trigger
+        for (trigger, modifications) in local.labels {
+            let merged = global.labels.entry(trigger).or_default();
+            for modification in modifications {
+                if !merged.contains(&modification) {
+                    merged.push(modification);
+                }
+            }
+        }
         // this field is merged
-        global.reviewers.extend(local.reviewers.into_iter());
+        global.reviewers.extend(local.reviewers);
         // this field is merged
-        global.try_users.extend(local.try_users.into_iter());
+        global.try_users.extend(local.try_users);
         // this field is overriden
         if !local.try_choosers.is_empty() {
             global.try_choosers = local.try_choosers;
         }
         // this field is overriden
-        global.fork_try = local.fork_try;
+        if local.fork_try.is_some() {
+            global.fork_try = local.fork_try;
+        }
+        // this field is overriden
+        if local.timeout.is_some() {
+            global.timeout = local.timeout;
+        }
+        // this field is merged
+        global.reviewer_teams.extend(local.reviewer_teams);
+        // this field is merged
+        global.try_teams.extend(local.try_teams);
+        // this field is overriden
+        if local.min_repo_permission.is_some() {
+            global.min_repo_permission = local.min_repo_permission;
+        }
+        // this field is not inherited
+        global.inherit = local.inherit;
+        // messages are overriden one by one
+        global.messages.merge(local.messages);
+        // these fields are overriden
+        if local.try_branch.is_some() {
+            global.try_branch = local.try_branch;
+        }
+        if local.try_merge_branch.is_some() {
+            global.try_merge_branch = local.try_merge_branch;
+        }
+        if local.commit_message.is_some() {
+            global.commit_message = local.commit_message;
+        }
         global
     }
 
-    async fn get(client: &Client, repo: &str, branch: &str) -> Option<Self> {
-        if let Ok(res) = client
-            .get(format!(
-                "https://raw.githubusercontent.com/{repo}/{branch}/{CONFIG_FILE_PATH}"
-            ))
-            .send()
-            .await
-        {
-            if let Ok(txt) = res.text().await {
-                tracing::debug!("Found config on {repo}/{branch}");
-                return toml::from_str(&txt).ok();
-            }
-        }
-        None
+    /// Time (in seconds) after which a pending try build is cancelled.
+    pub fn timeout(&self) -> u64 {
+        self.timeout.unwrap_or(DEFAULT_TIMEOUT)
+    }
+
+    /// Branch running CI checks of try builds.
+    pub fn try_branch(&self) -> &str {
+        self.try_branch.as_deref().unwrap_or(DEFAULT_TRY_BRANCH)
     }
 
-    #[allow(unused_mut)]
-    pub async fn get_all(repo: &GithubRepo) -> Option<Config> {
-        let client = Client::new();
-        let mut local = if let Some(loc) = Config::get(&client, &repo.to_string(), "master").await {
-            Some(loc)
-        } else {
-            Config::get(&client, &repo.to_string(), "main").await
+    /// Branch where the PR is merged before it is pushed to [`Config::try_branch`].
+    pub fn try_merge_branch(&self) -> &str {
+        self.try_merge_branch
+            .as_deref()
+            .unwrap_or(DEFAULT_TRY_MERGE_BRANCH)
+    }
+
+    /// The commit message uses `Co-authored-by` trailers,
+    /// so the commits of the PR need to be fetched.
+    pub fn wants_co_authors(&self) -> bool {
+        self.commit_message
+            .as_deref()
+            .is_some_and(|template| template.contains("{co_authored_by}"))
+    }
+
+    /// Message of the commit merging `pr`, `co_authors` are `Name <email>` of the PR commit authors.
+    pub fn commit_message(
+        &self,
+        pr: &PullRequest,
+        reviewer: &str,
+        co_authors: &[String],
+    ) -> String {
+        let template = self
+            .commit_message
+            .as_deref()
+            .unwrap_or(DEFAULT_COMMIT_MESSAGE);
+        let co_authored_by = co_authors
+            .iter()
+            .map(|author| format!("Co-authored-by: {author}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let message = render(
+            template,
+            &[
+                ("pr", &pr.number.to_string()),
+                ("label", &pr.head_label),
+                ("reviewer", reviewer),
+                ("title", &pr.title),
+                ("body", &pr.message),
+                ("co_authored_by", &co_authored_by),
+            ],
+        );
+        // an empty list of trailers would leave blank lines at the end
+        message.trim_end().to_owned()
+    }
+
+    /// Config file `path` on the commit `sha`, `None` if the commit has no such file.
+    async fn get<C: GitHubClient>(
+        gh: &mut C,
+        repo: &GithubRepo,
+        path: &str,
+        sha: &CommitSha,
+    ) -> Result<Option<Self>, ConfigError> {
+        let Some(txt) = gh.get_file_content(repo, path, sha.as_ref()).await? else {
+            return Ok(None);
         };
+        tracing::debug!("Found config {path} on {repo}@{sha}");
+        toml::from_str(&txt)
+            .map(Some)
+            .map_err(|error| ConfigError::Invalid {
+                repo: repo.clone(),
+                error,
+            })
+    }
 
-        #[cfg(feature = "servo")]
-        if local.is_none() {
-            Config::get(client, &repo.to_string(), "servo").await
+    /// Config file `path` on the head of `branch`, cached by the commit SHA.
+    ///
+    /// Only valid configs (or their absence) are cached.
+    async fn get_cached<C: GitHubClient>(
+        gh: &mut C,
+        repo: &GithubRepo,
+        path: &str,
+        branch: &str,
+    ) -> Result<Option<Self>, ConfigError> {
+        let sha = gh.get_branch_sha(repo, branch).await?;
+        if let Some(config) = gh.cache().config(repo, path, &sha).await {
+            tracing::debug!("Using cached config {path} of {repo}@{sha}");
+            return Ok(config);
+        }
+        let config = Config::get(gh, repo, path, &sha).await?;
+        gh.cache()
+            .set_config(repo, path, &sha, config.as_ref())
+            .await;
+        if let Some(config) = &config {
+            gh.cache().set_latest_config(repo, path, config).await;
+        }
+        Ok(config)
+    }
+
+    /// Organization config from the `branch` of its repository.
+    async fn get_inherited<C: GitHubClient>(
+        gh: &mut C,
+        source: &ConfigSource,
+        branch: &str,
+    ) -> Result<Option<Self>, ConfigError> {
+        let config = Config::get_cached(gh, &source.repo, &source.path, branch).await?;
+        if config.is_none() {
+            tracing::warn!("Inherited config {source} does not exist");
+        }
+        Ok(config)
+    }
+
+    /// Default branch of the repository, where the config is loaded from.
+    async fn default_branch<C: GitHubClient>(
+        gh: &mut C,
+        repo: &GithubRepo,
+    ) -> anyhow::Result<String> {
+        if let Some(branch) = gh.cache().default_branch(repo).await {
+            return Ok(branch);
         }
+        let branch = gh
+            .get_repository(repo)
+            .await?
+            .default_branch
+            .with_context(|| format!("{repo} has no default branch"))?;
+        gh.cache().set_default_branch(repo, &branch).await;
+        Ok(branch)
+    }
+
+    /// Loads the config of the repository and the organization config it inherits,
+    /// each with a description of where it was found.
+    async fn get_local_and_org<C: GitHubClient>(
+        gh: &mut C,
+        repo: &GithubRepo,
+    ) -> Result<(Option<(Config, String)>, Option<(Config, String)>), ConfigError> {
+        let branch = Config::default_branch(gh, repo).await?;
+        let local = Config::get_cached(gh, repo, CONFIG_FILE_PATH, &branch)
+            .await?
+            .map(|config| {
+                (
+                    config,
+                    format!("local `{repo}:{CONFIG_FILE_PATH}` on `{branch}`"),
+                )
+            });
 
-        let mut global = None;
         #[cfg(feature = "servo")]
-        {
-            let repo = format!("{ORG_CONFIG_REPO}/{}", repo.name());
-            if let Some(loc) = Config::get(&client, &repo, "master").await {
-                global = Some(loc)
-            } else {
-                global = Config::get(&client, &repo, "main").await
-            };
+        let local = match local {
+            Some(local) => Some(local),
+            None => Config::get_cached(gh, repo, CONFIG_FILE_PATH, "servo")
+                .await?
+                .map(|config| {
+                    (
+                        config,
+                        format!("local `{repo}:{CONFIG_FILE_PATH}` on `servo`"),
+                    )
+                }),
         };
-        match (local, global) {
-            (Some(loc), Some(glob)) => Some(Config::merge(glob, loc)),
-            (Some(loc), None) => Some(loc),
-            (None, Some(glob)) => Some(glob),
+
+        let source = local
+            .as_ref()
+            .and_then(|(local, _)| local.inherit.clone())
+            .or_else(|| ORG_CONFIG_REPO.get().cloned());
+        let global = match source {
+            // The organization config repository has no parent
+            Some(source) if source.repo != *repo => {
+                let branch = Config::default_branch(gh, &source.repo).await?;
+                Config::get_inherited(gh, &source, &branch)
+                    .await?
+                    .map(|config| (config, format!("org `{source}` on `{branch}`")))
+            }
             _ => None,
+        };
+        Ok((local, global))
+    }
+
+    pub async fn get_all<C: GitHubClient>(
+        gh: &mut C,
+        repo: &GithubRepo,
+    ) -> Result<Config, ConfigError> {
+        match Config::get_local_and_org(gh, repo).await? {
+            (Some((loc, _)), Some((glob, _))) => Ok(Config::merge(glob, loc)),
+            (Some((loc, _)), None) => Ok(loc),
+            (None, Some((glob, _))) => Ok(glob),
+            (None, None) => Err(ConfigError::Missing(repo.clone())),
+        }
+    }
+
+    /// Effective config of the repository from the last loaded config files,
+    /// without any request to GitHub.
+    ///
+    /// It may be outdated (or missing), so it is only good for things like messages.
+    pub async fn get_all_cached(cache: &Cache, repo: &GithubRepo) -> Option<Config> {
+        let local = cache.latest_config(repo, CONFIG_FILE_PATH).await;
+        let source = local
+            .as_ref()
+            .and_then(|local| local.inherit.clone())
+            .or_else(|| ORG_CONFIG_REPO.get().cloned());
+        let global = match source {
+            Some(source) if source.repo != *repo => {
+                cache.latest_config(&source.repo, &source.path).await
+            }
+            _ => None,
+        };
+        match (local, global) {
+            (Some(local), Some(global)) => Some(Config::merge(global, local)),
+            (local, global) => local.or(global),
+        }
+    }
+
+    /// Effective config of the repository as TOML,
+    /// every key is annotated with the config files it comes from.
+    pub async fn get_all_annotated<C: GitHubClient>(
+        gh: &mut C,
+        repo: &GithubRepo,
+    ) -> Result<String, ConfigError> {
+        let (local, global) = Config::get_local_and_org(gh, repo).await?;
+        let sources: Vec<(String, toml::Table)> = global
+            .iter()
+            .chain(local.iter())
+            .map(|(config, origin)| (origin.clone(), to_table(config)))
+            .collect();
+        let config = match (local, global) {
+            (Some((loc, _)), Some((glob, _))) => Config::merge(glob, loc),
+            (Some((loc, _)), None) => loc,
+            (None, Some((glob, _))) => glob,
+            (None, None) => return Err(ConfigError::Missing(repo.clone())),
+        };
+        Ok(annotate(&config, &sources))
+    }
+}
+
+/// Serializes the config, with sets sorted so that they can be compared.
+fn to_table(config: &Config) -> toml::Table {
+    let Ok(toml::Value::Table(mut table)) = toml::Value::try_from(config) else {
+        return toml::Table::new();
+    };
+    for (_, value) in table.iter_mut() {
+        if let toml::Value::Array(array) = value {
+            array.sort_by_key(|item| item.to_string());
         }
     }
+    table
+}
+
+/// The `key` is set in the `source` config file.
+///
+/// Optional keys are only serialized when they are present in the file,
+/// whatever their value. Lists and tables always are, but an empty one
+/// takes no part in the merge, so it is not attributed to the file.
+fn is_set(source: &toml::Table, key: &str) -> bool {
+    match source.get(key) {
+        None => false,
+        Some(toml::Value::Array(array)) => !array.is_empty(),
+        Some(toml::Value::Table(table)) => !table.is_empty(),
+        Some(_) => true,
+    }
+}
+
+/// Renders the config, annotating every key with the `sources` it comes from.
+///
+/// Overridden keys come from the sources with the same value,
+/// merged keys come from all sources that set them.
+fn annotate(config: &Config, sources: &[(String, toml::Table)]) -> String {
+    // Plain keys must precede tables, empty tables have nothing to show
+    let (tables, values): (Vec<_>, Vec<_>) = to_table(config)
+        .into_iter()
+        .filter(|(_, value)| !matches!(value, toml::Value::Table(table) if table.is_empty()))
+        .partition(|(_, value)| value.is_table());
+    let mut rendered = String::new();
+    for (key, value) in values.into_iter().chain(tables) {
+        let set: Vec<&str> = sources
+            .iter()
+            .filter(|(_, table)| is_set(table, &key))
+            .map(|(origin, _)| origin.as_str())
+            .collect();
+        let same: Vec<&str> = sources
+            .iter()
+            .filter(|(origin, table)| {
+                set.contains(&origin.as_str()) && table.get(&key) == Some(&value)
+            })
+            .map(|(origin, _)| origin.as_str())
+            .collect();
+        let origins = match (same.is_empty(), set.is_empty()) {
+            (false, _) => same.join(", "),
+            (true, false) => set.join(", "),
+            (true, true) => "default".to_string(),
+        };
+        let mut table = toml::Table::new();
+        table.insert(key, value);
+        rendered += &format!(
+            "# {origins}\n{}",
+            toml::to_string(&table).unwrap_or_default()
+        );
+    }
+    rendered
+}
+
+fn serialize_labels<S>(
+    labels: &HashMap<LabelTrigger, Vec<LabelModification>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let labels: BTreeMap<&str, Vec<String>> = labels
+        .iter()
+        .map(|(trigger, modifications)| {
+            let trigger = match trigger {
+                LabelTrigger::TryBuildStarted => "try",
+                LabelTrigger::TryBuildSucceeded => "try_succeed",
+                LabelTrigger::TryBuildFailed => "try_failed",
+                LabelTrigger::MergeConflict => "merge_conflict",
+            };
+            let modifications = modifications
+                .iter()
+                .map(|modification| match modification {
+                    LabelModification::Add(label) => format!("+{label}"),
+                    LabelModification::Remove(label) => format!("-{label}"),
+                })
+                .collect();
+            (trigger, modifications)
+        })
+        .collect();
+    labels.serialize(serializer)
 }
 
 fn deserialize_labels<'de, D>(
@@ -136,6 +704,7 @@ where
         Try,
         TrySucceed,
         TryFailed,
+        MergeConflict,
     }
 
     impl From<Trigger> for LabelTrigger {
@@ -144,6 +713,7 @@ where
                 Trigger::Try => LabelTrigger::TryBuildStarted,
                 Trigger::TrySucceed => LabelTrigger::TryBuildSucceeded,
                 Trigger::TryFailed => LabelTrigger::TryBuildFailed,
+                Trigger::MergeConflict => LabelTrigger::MergeConflict,
             }
         }
     }
@@ -198,9 +768,17 @@ where
 
 #[cfg(test)]
 mod tests {
-    use std::collections::BTreeMap;
+    use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
-    use crate::config::Config;
+    use crate::config::{
+        annotate, to_table, Config, ConfigDiagnostic, ConfigSource, CONFIG_FILE_PATH,
+        DEFAULT_TIMEOUT, DEFAULT_TRY_BRANCH, DEFAULT_TRY_MERGE_BRANCH, KNOWN_KEYS,
+    };
+    use crate::github::{
+        Branch, CommitSha, GithubRepo, LabelModification, LabelTrigger, PullRequest,
+    };
+    use crate::messages::Messages;
+    use crate::permissions::RepoPermission;
 
     #[test]
     fn deserialize_empty() {
@@ -210,6 +788,229 @@ mod tests {
         assert!(config.reviewers.is_empty());
         assert!(config.try_users.is_empty());
         assert!(config.try_choosers.is_empty());
+        assert_eq!(config.timeout(), DEFAULT_TIMEOUT);
+    }
+
+    #[test]
+    fn deserialize_org_permissions() {
+        let content = r#"reviewer_teams = ["servo/reviewers"]
+min_repo_permission = "write"
+"#;
+        let config = load_config(content);
+        assert!(config.reviewer_teams.contains("servo/reviewers"));
+        assert!(config.try_teams.is_empty());
+        assert_eq!(config.min_repo_permission, Some(RepoPermission::Write));
+    }
+
+    #[test]
+    fn deserialize_inherit() {
+        let config = load_config(r#"inherit = "servo/.github:bors/servo.toml""#);
+        assert_eq!(
+            config.inherit,
+            Some(ConfigSource {
+                repo: GithubRepo::new("servo", ".github"),
+                path: "bors/servo.toml".to_string(),
+            })
+        );
+        let config = load_config(r#"inherit = "servo/saltfs""#);
+        assert_eq!(config.inherit.unwrap().path, CONFIG_FILE_PATH);
+        assert!(toml::from_str::<Config>(r#"inherit = "servo""#).is_err());
+    }
+
+    #[test]
+    fn merge_inherited() {
+        let global = load_config(
+            r#"reviewers = ["alice"]
+try_choosers = ["linux"]
+timeout = 60
+fork_try = true
+
+[labels]
+try = ["+foo"]
+"#,
+        );
+        let local = load_config(
+            r#"reviewers = ["bob"]
+try_choosers = ["mac"]
+
+[labels]
+try = ["+foo", "-bar"]
+"#,
+        );
+        let config = Config::merge(global, local);
+        assert!(config.reviewers.contains("alice"));
+        assert!(config.reviewers.contains("bob"));
+        assert_eq!(config.try_choosers.len(), 1);
+        assert!(config.try_choosers.contains("mac"));
+        assert_eq!(config.timeout(), 60);
+        assert_eq!(config.fork_try, Some(true));
+        assert_eq!(
+            config.labels[&LabelTrigger::TryBuildStarted],
+            vec![
+                LabelModification::Add("foo".to_string()),
+                LabelModification::Remove("bar".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn annotate_sources() {
+        let global = r#"reviewers = ["alice"]
+timeout = 60
+"#;
+        let local = r#"reviewers = ["bob"]
+timeout = 30
+fork_try = true
+
+[labels]
+try = ["+foo"]
+"#;
+        let sources = vec![
+            ("org".to_string(), to_table(&load_config(global))),
+            ("local".to_string(), to_table(&load_config(local))),
+        ];
+        let config = Config::merge(load_config(global), load_config(local));
+        insta::assert_snapshot!(annotate(&config, &sources), @r###"
+        # local
+        fork_try = true
+        # default
+        reviewer_teams = []
+        # org, local
+        reviewers = ["alice", "bob"]
+        # local
+        timeout = 30
+        # default
+        try_choosers = []
+        # default
+        try_teams = []
+        # default
+        try_users = []
+        # local
+        [labels]
+        try = ["+foo"]
+        "###);
+    }
+
+    #[test]
+    fn annotate_overridden_false() {
+        let global = "fork_try = true\n";
+        let local = "fork_try = false\n";
+        let sources = vec![
+            ("org".to_string(), to_table(&load_config(global))),
+            ("local".to_string(), to_table(&load_config(local))),
+        ];
+        let config = Config::merge(load_config(global), load_config(local));
+        assert!(annotate(&config, &sources).starts_with("# local\nfork_try = false\n"));
+    }
+
+    #[test]
+    fn known_keys() {
+        // Every field is listed, so that a new one cannot be forgotten in `KNOWN_KEYS`
+        let config = Config {
+            labels: HashMap::new(),
+            reviewers: HashSet::new(),
+            try_users: HashSet::new(),
+            try_choosers: HashSet::new(),
+            fork_try: Some(true),
+            timeout: Some(60),
+            reviewer_teams: HashSet::new(),
+            try_teams: HashSet::new(),
+            min_repo_permission: Some(RepoPermission::Write),
+            inherit: Some("servo/.github".parse().unwrap()),
+            messages: Messages::default(),
+            try_branch: Some("try".to_string()),
+            try_merge_branch: Some("try-merge".to_string()),
+            commit_message: Some("{title}".to_string()),
+        };
+        let keys: BTreeSet<String> = to_table(&config).keys().cloned().collect();
+        let known: BTreeSet<String> = KNOWN_KEYS.iter().map(|key| key.to_string()).collect();
+        assert_eq!(keys, known);
+    }
+
+    #[test]
+    fn deserialize_messages() {
+        let content = r#"[messages]
+ping = "Hi {user}"
+"#;
+        let config = load_config(content);
+        assert_eq!(config.messages.ping.as_deref(), Some("Hi {user}"));
+        assert!(config.messages.try_started.is_none());
+    }
+
+    #[test]
+    fn deserialize_try_branches() {
+        let config = load_config("");
+        assert_eq!(config.try_branch(), DEFAULT_TRY_BRANCH);
+        assert_eq!(config.try_merge_branch(), DEFAULT_TRY_MERGE_BRANCH);
+
+        let content = r#"
+try_branch = "bors/try"
+try_merge_branch = "bors/try-merge"
+"#;
+        let config = load_config(content);
+        assert_eq!(config.try_branch(), "bors/try");
+        assert_eq!(config.try_merge_branch(), "bors/try-merge");
+    }
+
+    fn pull_request() -> PullRequest {
+        let branch = |name: &str| Branch {
+            name: name.to_string(),
+            sha: CommitSha("abc".to_string()),
+        };
+        PullRequest {
+            number: 5,
+            head_label: "alice:feature".to_string(),
+            head: branch("feature"),
+            base: branch("main"),
+            title: "Add feature".to_string(),
+            message: "Description".to_string(),
+            open: true,
+            mergeable: Some(true),
+        }
+    }
+
+    #[test]
+    fn default_commit_message() {
+        let config = load_config("");
+        assert!(!config.wants_co_authors());
+        assert_eq!(
+            config.commit_message(&pull_request(), "bob", &[]),
+            "Auto merge of #5 - alice:feature, r=bob\nAdd feature\n\nDescription"
+        );
+    }
+
+    #[test]
+    fn commit_message_co_authors() {
+        let content = r#"
+commit_message = """
+{title} (#{pr})
+
+{body}
+
+{co_authored_by}
+"""
+"#;
+        let config = load_config(content);
+        assert!(config.wants_co_authors());
+        assert_eq!(
+            config.commit_message(
+                &pull_request(),
+                "bob",
+                &["Alice <alice@example.com>".to_string()]
+            ),
+            "Add feature (#5)\n\nDescription\n\nCo-authored-by: Alice <alice@example.com>"
+        );
+        assert_eq!(
+            config.commit_message(&pull_request(), "bob", &[]),
+            "Add feature (#5)\n\nDescription"
+        );
+    }
+
+    #[test]
+    fn deserialize_timeout() {
+        let content = "timeout = 3600";
+        let config = load_config(content);
+        assert_eq!(config.timeout(), 3600);
     }
 
     #[test]
@@ -246,6 +1047,21 @@ try_failed = []
         "###);
     }
 
+    #[test]
+    fn deserialize_merge_conflict_label() {
+        let content = r#"[labels]
+merge_conflict = ["+has-merge-conflict", "-S-awaiting-merge"]
+"#;
+        let config = load_config(content);
+        assert_eq!(
+            config.labels[&LabelTrigger::MergeConflict],
+            vec![
+                LabelModification::Add("has-merge-conflict".to_string()),
+                LabelModification::Remove("S-awaiting-merge".to_string()),
+            ]
+        );
+    }
+
     #[test]
     #[should_panic(expected = "Label modification must start with `+` or `-`")]
     fn deserialize_labels_missing_prefix() {
@@ -261,6 +1077,154 @@ try = ["foo"]
         load_config(content);
     }
 
+    #[test]
+    fn serialize_roundtrip() {
+        let content = r#"reviewers = ["sagudev"]
+try_users = ["foo"]
+
+[labels]
+try = ["+foo", "-bar"]
+"#;
+        let config = load_config(content);
+        let config = load_config(&toml::to_string(&config).unwrap());
+        assert!(config.reviewers.contains("sagudev"));
+        assert!(config.try_users.contains("foo"));
+        insta::assert_debug_snapshot!(config.labels, @r###"
+        {
+            TryBuildStarted: [
+                Add(
+                    "foo",
+                ),
+                Remove(
+                    "bar",
+                ),
+            ],
+        }
+        "###);
+    }
+
+    #[test]
+    fn validate_valid() {
+        let validation = Config::validate(
+            r#"reviewers = ["sagudev"]
+
+[labels]
+try = ["+foo"]
+"#,
+            "main",
+        );
+        assert!(validation.errors.is_empty());
+        assert!(validation.warnings.is_empty());
+    }
+
+    #[test]
+    fn validate_invalid_type() {
+        let validation = Config::validate(
+            r#"reviewers = ["sagudev"]
+timeout = "1h"
+"#,
+            "main",
+        );
+        assert_eq!(validation.errors.len(), 1);
+        assert_eq!(validation.errors[0].line, Some(2));
+        assert!(validation.errors[0].message.starts_with("`timeout`: "));
+    }
+
+    #[test]
+    fn validate_unknown_message() {
+        let validation = Config::validate(
+            r#"reviewers = ["sagudev"]
+
+[messages]
+ping = "Hi {user}"
+pnig = "Hi {user}"
+"#,
+            "main",
+        );
+        assert!(validation.errors.is_empty());
+        assert_eq!(
+            validation.warnings,
+            vec![ConfigDiagnostic {
+                line: Some(5),
+                message: "Unknown key `messages.pnig` is ignored".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn validate_invalid_label() {
+        let validation = Config::validate(
+            r#"reviewers = ["sagudev"]
+
+[labels]
+try = ["foo"]
+"#,
+            "main",
+        );
+        assert_eq!(validation.errors.len(), 1);
+        assert_eq!(validation.errors[0].line, Some(4));
+    }
+
+    #[test]
+    fn validate_try_branches() {
+        let validation = Config::validate(
+            r#"try_branch = "bors"
+try_merge_branch = "bors"
+"#,
+            "main",
+        );
+        assert_eq!(
+            validation.errors,
+            vec![ConfigDiagnostic {
+                line: Some(1),
+                message: "`try_branch` and `try_merge_branch` must be different branches"
+                    .to_string()
+            }]
+        );
+
+        let validation = Config::validate(r#"try_merge_branch = "main""#, "main");
+        assert_eq!(
+            validation.errors,
+            vec![ConfigDiagnostic {
+                line: Some(1),
+                message:
+                    "`try_merge_branch` must not be the default branch `main`, bors overwrites it"
+                        .to_string()
+            }]
+        );
+
+        let validation = Config::validate("", DEFAULT_TRY_BRANCH);
+        assert_eq!(validation.errors.len(), 1);
+        assert_eq!(validation.errors[0].line, None);
+    }
+
+    #[test]
+    fn validate_unknown_keys() {
+        let validation = Config::validate(
+            r#"reviewers = ["sagudev"]
+reviwers = ["typo"]
+
+[lables]
+try = ["+foo"]
+"#,
+            "main",
+        );
+        assert!(validation.errors.is_empty());
+        assert_eq!(
+            validation.warnings,
+            vec![
+                ConfigDiagnostic {
+                    line: Some(4),
+                    message: "Unknown key `lables` is ignored".to_string()
+                },
+                ConfigDiagnostic {
+                    line: Some(2),
+                    message: "Unknown key `reviwers` is ignored".to_string()
+                },
+            ]
+        );
+    }
+
     fn load_config(config: &str) -> Config {
         toml::from_str(config).unwrap()
     }
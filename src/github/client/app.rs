@@ -1,15 +1,45 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
 use std::time::UNIX_EPOCH;
 
 use anyhow::{anyhow, Context, Result};
+use chrono::DateTime;
 use jsonwebtoken::{Algorithm, Header};
+use reqwest::{Method, RequestBuilder};
 
-use super::GitHubClient;
+use super::rate::Account;
+use super::retry;
+use super::{GitHubClient, StatusError};
+use crate::cache::Cache;
 use crate::config::{APP_ID, CMD_PREFIX, PRIVATE_KEY};
-use crate::github::API_ENDPOINT;
-use crate::models::AppId;
+use crate::github::{GithubRepo, API_ENDPOINT};
+use crate::models::apps::App;
+use crate::models::{AppId, Installation, InstallationId, InstallationToken};
+use crate::utils::time::now;
 
-/// Provides access to a single app installation (repository) using the GitHub API.
-pub struct AppClient(String);
+/// Login of the app's bot user (`<slug>[bot]`), resolved on first use.
+static LOGIN: OnceLock<String> = OnceLock::new();
+
+/// Installation tokens are refreshed this many seconds before they expire.
+const TOKEN_EXPIRY_MARGIN: i64 = 60;
+
+/// Provides access to app installations (repositories) using the GitHub API.
+///
+/// App endpoints (`/app/...`) are called with the app's JWT, repository endpoints
+/// (`/repos/{owner}/{name}/...`) with an access token of the installation on that repository
+/// and organization endpoints (`/orgs/{org}/...`) with the one on that organization.
+pub struct AppClient {
+    /// JWT authenticating the app itself.
+    jwt: String,
+    /// Installations of repositories that were already called.
+    installations: HashMap<GithubRepo, InstallationId>,
+    /// Installations of organizations that were already called.
+    org_installations: HashMap<String, InstallationId>,
+    /// Installation access tokens, valid until their `expires_at`.
+    tokens: HashMap<InstallationId, InstallationToken>,
+    /// Keeps installations, tokens and login across requests.
+    cache: Cache,
+}
 
 impl AppClient {
     /// Create a JSON Web Token that can be used to authenticate an a GitHub application.
@@ -45,15 +75,185 @@ impl AppClient {
         Ok(jsonwebtoken::encode(&header, &claims, &key)?)
     }
 
-    pub fn new() -> Result<Self> {
+    pub fn new(cache: Cache) -> Result<Self> {
         if !Self::is_available() {
             return Err(anyhow!("APP NOt available!"));
         }
-        Ok(Self(Self::generate_bearer_token(
-            AppId(APP_ID.get().unwrap().parse()?),
-            PRIVATE_KEY.get().unwrap(),
-        )?))
+        Ok(Self {
+            jwt: Self::generate_bearer_token(
+                AppId(APP_ID.get().unwrap().parse()?),
+                PRIVATE_KEY.get().unwrap(),
+            )?,
+            installations: HashMap::new(),
+            org_installations: HashMap::new(),
+            tokens: HashMap::new(),
+            cache,
+        })
+    }
+
+    fn request(method: Method, end: &str, token: &str) -> RequestBuilder {
+        reqwest::Client::new()
+            .request(method, API_ENDPOINT.to_owned() + end)
+            .bearer_auth(token)
+            .header("X-GitHub-Api-Version", "2022-11-28")
+            .header("Accept", "application/vnd.github+json")
+            .header("User-Agent", CMD_PREFIX.get().unwrap())
+    }
+
+    /// Finds the installation of the app on the given repository.
+    // Documentation: https://docs.github.com/en/rest/apps/apps?apiVersion=2022-11-28#get-a-repository-installation-for-the-authenticated-app
+    async fn installation_id(&mut self, repo: &GithubRepo) -> Result<InstallationId> {
+        if let Some(id) = self.installations.get(repo) {
+            return Ok(*id);
+        }
+        if let Some(id) = self.cache.installation(repo).await {
+            self.installations.insert(repo.clone(), id);
+            return Ok(id);
+        }
+        let jwt = &self.jwt;
+        let end = format!("/repos/{repo}/installation");
+        let res = retry::send(Account::App, Method::GET, |method| {
+            Self::request(method, &end, jwt)
+        })
+        .await?;
+        if !res.status().is_success() {
+            return Err(anyhow::Error::new(StatusError(res.status())))
+                .with_context(|| format!("App is not installed on {repo}"));
+        }
+        let installation: Installation = res
+            .json()
+            .await
+            .with_context(|| format!("Could not parse installation of {repo}"))?;
+        tracing::debug!("Repository {repo} uses installation {}", installation.id);
+        self.cache.set_installation(repo, installation.id).await;
+        self.installations.insert(repo.clone(), installation.id);
+        Ok(installation.id)
+    }
+
+    /// Finds the installation of the app on the given organization.
+    // Documentation: https://docs.github.com/en/rest/apps/apps?apiVersion=2022-11-28#get-an-organization-installation-for-the-authenticated-app
+    async fn org_installation_id(&mut self, org: &str) -> Result<InstallationId> {
+        if let Some(id) = self.org_installations.get(org) {
+            return Ok(*id);
+        }
+        if let Some(id) = self.cache.org_installation(org).await {
+            self.org_installations.insert(org.to_owned(), id);
+            return Ok(id);
+        }
+        let jwt = &self.jwt;
+        let end = format!("/orgs/{org}/installation");
+        let res = retry::send(Account::App, Method::GET, |method| {
+            Self::request(method, &end, jwt)
+        })
+        .await?;
+        if !res.status().is_success() {
+            return Err(anyhow::Error::new(StatusError(res.status())))
+                .with_context(|| format!("App is not installed on {org}"));
+        }
+        let installation: Installation = res
+            .json()
+            .await
+            .with_context(|| format!("Could not parse installation of {org}"))?;
+        tracing::debug!("Organization {org} uses installation {}", installation.id);
+        self.cache.set_org_installation(org, installation.id).await;
+        self.org_installations
+            .insert(org.to_owned(), installation.id);
+        Ok(installation.id)
+    }
+
+    /// Exchanges the JWT for an access token of the installation, reusing it until it expires.
+    // Documentation: https://docs.github.com/en/rest/apps/apps?apiVersion=2022-11-28#create-an-installation-access-token-for-an-app
+    async fn installation_token(&mut self, installation: InstallationId) -> Result<String> {
+        if let Some(token) = self.tokens.get(&installation) {
+            if valid_for(token) > 0 {
+                return Ok(token.token.clone());
+            }
+        }
+        if let Some(token) = self.cache.installation_token(installation).await {
+            if valid_for(&token) > 0 {
+                let secret = token.token.clone();
+                self.tokens.insert(installation, token);
+                return Ok(secret);
+            }
+        }
+        let jwt = &self.jwt;
+        let end = format!("/app/installations/{installation}/access_tokens");
+        let res = retry::send(Account::App, Method::POST, |method| {
+            Self::request(method, &end, jwt)
+        })
+        .await?;
+        if !res.status().is_success() {
+            return Err(
+                anyhow::Error::new(StatusError(res.status())).context(format!(
+                    "Cannot create token for installation {installation}: {:#?}",
+                    res.text().await
+                )),
+            );
+        }
+        let token: InstallationToken = res
+            .json()
+            .await
+            .context("Could not parse installation token")?;
+        let secret = token.token.clone();
+        if let Ok(ttl) = u64::try_from(valid_for(&token)) {
+            self.cache
+                .set_installation_token(installation, &token, ttl)
+                .await;
+        }
+        self.tokens.insert(installation, token);
+        Ok(secret)
     }
+
+    /// Picks the token to authenticate a call of the given endpoint.
+    async fn token_for(&mut self, end: &str) -> Result<(String, Account)> {
+        let installation = if let Some(repo) = repo_of_endpoint(end) {
+            self.installation_id(&repo).await?
+        } else if let Some(org) = org_of_endpoint(end) {
+            // The app JWT is not accepted on organization endpoints
+            self.org_installation_id(&org).await?
+        } else {
+            return Ok((self.jwt.clone(), Account::App));
+        };
+        let token = self.installation_token(installation).await?;
+        Ok((token, Account::Installation(installation)))
+    }
+
+    /// Account that would be charged for calls on the repository,
+    /// `None` if its installation was not looked up yet.
+    pub(super) fn known_account(&self, repo: &GithubRepo) -> Option<Account> {
+        self.installations
+            .get(repo)
+            .map(|installation| Account::Installation(*installation))
+    }
+}
+
+/// Extracts the repository from `/repos/{owner}/{name}/...` endpoints.
+pub(super) fn repo_of_endpoint(end: &str) -> Option<GithubRepo> {
+    let mut parts = end.strip_prefix("/repos/")?.split(['/', '?']);
+    match (parts.next(), parts.next()) {
+        (Some(owner), Some(name)) if !owner.is_empty() && !name.is_empty() => {
+            Some(GithubRepo::new(owner, name))
+        }
+        _ => None,
+    }
+}
+
+/// Extracts the organization from `/orgs/{org}/...` endpoints.
+pub(super) fn org_of_endpoint(end: &str) -> Option<String> {
+    let org = end.strip_prefix("/orgs/")?.split(['/', '?']).next()?;
+    (!org.is_empty()).then(|| org.to_lowercase())
+}
+
+/// Seconds for which the token can still be used (not positive if it must be refreshed).
+fn valid_for(token: &InstallationToken) -> i64 {
+    let Some(expires_at) = token
+        .expires_at
+        .as_deref()
+        .and_then(|expires_at| DateTime::parse_from_rfc3339(expires_at).ok())
+    else {
+        return 0;
+    };
+    expires_at.timestamp() - TOKEN_EXPIRY_MARGIN - now().timestamp()
 }
 
 #[async_trait::async_trait(?Send)]
@@ -63,15 +263,11 @@ impl GitHubClient for AppClient {
     }
 
     async fn get(&mut self, end: &str) -> Result<reqwest::Response> {
-        reqwest::Client::new()
-            .get(API_ENDPOINT.to_owned() + end)
-            .bearer_auth(&self.0)
-            .header("X-GitHub-Api-Version", "2022-11-28")
-            .header("Accept", "application/vnd.github+json")
-            .header("User-Agent", CMD_PREFIX.get().unwrap())
-            .send()
-            .await
-            .map_err(|e| anyhow::anyhow!(e))
+        let (token, account) = self.token_for(end).await?;
+        retry::send(account, Method::GET, |method| {
+            Self::request(method, end, &token)
+        })
+        .await
     }
 
     async fn post<D: serde::Serialize + Sized>(
@@ -79,16 +275,11 @@ impl GitHubClient for AppClient {
         end: &str,
         data: &D,
     ) -> Result<reqwest::Response> {
-        reqwest::Client::new()
-            .post(API_ENDPOINT.to_owned() + end)
-            .bearer_auth(&self.0)
-            .header("X-GitHub-Api-Version", "2022-11-28")
-            .header("Accept", "application/vnd.github+json")
-            .header("User-Agent", CMD_PREFIX.get().unwrap())
-            .json(data)
-            .send()
-            .await
-            .map_err(|e| anyhow::anyhow!(e))
+        let (token, account) = self.token_for(end).await?;
+        retry::send(account, Method::POST, |method| {
+            Self::request(method, end, &token).json(data)
+        })
+        .await
     }
 
     async fn patch<D: serde::Serialize + Sized>(
@@ -96,17 +287,113 @@ impl GitHubClient for AppClient {
         end: &str,
         data: &D,
     ) -> Result<reqwest::Response> {
-        reqwest::Client::new()
-            .patch(API_ENDPOINT.to_owned() + end)
-            .bearer_auth(&self.0)
-            .header("X-GitHub-Api-Version", "2022-11-28")
-            .header("Accept", "application/vnd.github+json")
-            .header("User-Agent", CMD_PREFIX.get().unwrap())
-            .json(data)
-            .send()
-            .await
-            .map_err(|e| anyhow::anyhow!(e))
+        let (token, account) = self.token_for(end).await?;
+        retry::send(account, Method::PATCH, |method| {
+            Self::request(method, end, &token).json(data)
+        })
+        .await
+    }
+
+    async fn delete(&mut self, end: &str) -> Result<reqwest::Response> {
+        let (token, account) = self.token_for(end).await?;
+        retry::send(account, Method::DELETE, |method| {
+            Self::request(method, end, &token)
+        })
+        .await
+    }
+
+    /// GraphQL has no repository in the endpoint, so the installation is picked by `repo`.
+    async fn post_graphql<D: serde::Serialize + Sized>(
+        &mut self,
+        repo: &GithubRepo,
+        body: &D,
+        read_only: bool,
+    ) -> Result<reqwest::Response> {
+        let installation = self.installation_id(repo).await?;
+        let token = self.installation_token(installation).await?;
+        let account = Account::Installation(installation);
+        let request = |method| Self::request(method, "/graphql", &token).json(body);
+        if read_only {
+            retry::send_idempotent(account, Method::POST, request).await
+        } else {
+            retry::send(account, Method::POST, request).await
+        }
+    }
+
+    async fn login(&mut self) -> Result<String> {
+        if let Some(login) = LOGIN.get() {
+            return Ok(login.clone());
+        }
+        let login = match self.cache.login("app").await {
+            Some(login) => login,
+            None => {
+                let app: App = self
+                    .get("/app")
+                    .await?
+                    .json()
+                    .await
+                    .context("Could not parse authenticated app")?;
+                let slug = app
+                    .slug
+                    .ok_or_else(|| anyhow!("App {} has no slug", app.id))?;
+                let login = format!("{slug}[bot]");
+                self.cache.set_login("app", &login).await;
+                login
+            }
+        };
+        Ok(LOGIN.get_or_init(|| login).clone())
     }
+
+    fn cache(&self) -> &Cache {
+        &self.cache
+    }
+}
+
+#[test]
+fn endpoint_repository() {
+    assert_eq!(
+        repo_of_endpoint("/repos/Servo/servo/issues/1/comments"),
+        Some(GithubRepo::new("servo", "servo"))
+    );
+    assert_eq!(
+        repo_of_endpoint("/repos/servo/servo"),
+        Some(GithubRepo::new("servo", "servo"))
+    );
+    assert_eq!(repo_of_endpoint("/repos/servo"), None);
+    assert_eq!(repo_of_endpoint("/app"), None);
+    assert_eq!(
+        org_of_endpoint("/orgs/Servo/teams/x/memberships/y"),
+        Some("servo".to_string())
+    );
+    assert_eq!(org_of_endpoint("/orgs/"), None);
+    assert_eq!(org_of_endpoint("/repos/servo/servo"), None);
+}
+
+#[tokio::test]
+async fn org_endpoint_token() {
+    let installation = InstallationId(42);
+    let token: InstallationToken = serde_json::from_value(serde_json::json!({
+        "token": "installation-token",
+        "expires_at": "2999-01-01T00:00:00Z",
+        "permissions": {},
+    }))
+    .unwrap();
+    let mut client = AppClient {
+        jwt: "jwt".to_string(),
+        installations: HashMap::new(),
+        org_installations: HashMap::from([("servo".to_string(), installation)]),
+        tokens: HashMap::from([(installation, token)]),
+        cache: Cache::default(),
+    };
+    let (token, account) = client
+        .token_for("/orgs/servo/teams/x/memberships/y")
+        .await
+        .unwrap();
+    assert_eq!(token, "installation-token");
+    assert_eq!(account, Account::Installation(installation));
+    let (token, account) = client.token_for("/app").await.unwrap();
+    assert_eq!(token, "jwt");
+    assert_eq!(account, Account::App);
 }
 
 #[tokio::test]
@@ -145,10 +432,10 @@ xCUhwhCTXk7zQJqY1UKGjVEOs/1F6GITwQm1ZCC7WSdLedfoZHpe9w==
         )
         .unwrap();
     CMD_PREFIX.set("@bo-homu".to_string()).unwrap();
-    let mut cli = AppClient::new().unwrap();
-    println!("tok: {}", cli.0);
+    let mut cli = AppClient::new(Cache::default()).unwrap();
+    println!("tok: {}", cli.jwt);
     cli.post_comment(
-        &crate::github::GithubRepo {
+        &GithubRepo {
             owner: "bo-playground".to_string(),
             name: "musical-enigma".to_string(),
         },
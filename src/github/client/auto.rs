@@ -2,63 +2,163 @@
 //! also based upon availability
 
 use std::cell::OnceCell;
+use std::time::Duration;
 
 use anyhow::Result;
 use thiserror::Error;
+use worker::Delay;
 
+use super::app::repo_of_endpoint;
+use super::rate::{self, Account};
 use super::{AppClient, GitHubClient, TokenClient};
+use crate::cache::Cache;
 use crate::github::misc::{CheckSuite, Reference};
 use crate::github::{CommitSha, GithubRepo, PullRequest, PullRequestNumber};
-use crate::models::RunId;
+use crate::models::checks::CheckRunOutput;
+use crate::models::repos::CommitAuthor;
+use crate::models::{Rate, RunId};
+use crate::store::Store;
+use crate::utils::time::now;
+
+/// Longest time (in seconds) to wait for a rate limit reset before giving up.
+const MAX_RATE_LIMIT_WAIT: u64 = 60;
 
 #[derive(Error, Debug)]
 pub enum AutoClientError {
     #[error("No authorized client available.")]
     NoClient,
+    #[error("API rate limit exhausted, resets in {0}s.")]
+    RateLimited(u64),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Choice {
+    Pat,
+    App,
 }
 
-// selectors
+// selectors, the first client is preferred if both have the same budget left
 macro_rules! pat_app {
-    ($sef:expr,$fn:ident($($arg:expr),*)) => {
-        if TokenClient::is_available() {
-            $sef.token.$fn($($arg),*).await
-        } else if let Ok(app_cli) = $sef.app_get_or_init() {
-            app_cli.$fn($($arg),*).await
-        } else {
-            Err(AutoClientError::NoClient.into())
+    ($sef:expr,$repo:expr,$fn:ident($($arg:expr),*)) => {
+        match $sef.choose($repo, Choice::Pat).await? {
+            Choice::Pat => $sef.token.$fn($($arg),*).await,
+            Choice::App => $sef.app_get_or_init()?.$fn($($arg),*).await,
         }
     };
 }
 
 macro_rules! app_pat {
-    ($sef:expr,$fn:ident($($arg:expr),*)) => {
-        if let Ok(app_cli) = $sef.app_get_or_init() {
-            app_cli.$fn($($arg),*).await
-        } else if TokenClient::is_available() {
-            $sef.token.$fn($($arg),*).await
-        } else {
-            Err(AutoClientError::NoClient.into())
+    ($sef:expr,$repo:expr,$fn:ident($($arg:expr),*)) => {
+        match $sef.choose($repo, Choice::App).await? {
+            Choice::Pat => $sef.token.$fn($($arg),*).await,
+            Choice::App => $sef.app_get_or_init()?.$fn($($arg),*).await,
         }
     };
 }
 
+/// Requests left in the budget, unknown budget is considered to be the full hourly limit.
+fn remaining(rate: &Option<Rate>, account: &Account) -> usize {
+    rate.as_ref()
+        .map_or_else(|| account.default_limit(), |rate| rate.remaining)
+}
+
 pub struct AutoGitHubClient {
     token: TokenClient,
     app: OnceCell<AppClient>,
+    cache: Cache,
 }
 
 impl AutoGitHubClient {
-    pub fn new() -> Self {
+    pub fn new(store: Store) -> Self {
+        let cache = Cache::new(store);
         Self {
-            token: TokenClient,
+            token: TokenClient::new(cache.clone()),
             app: OnceCell::new(),
+            cache,
         }
     }
 
     fn app_get_or_init(&mut self) -> Result<&mut AppClient> {
-        self.app.get_or_init(|| AppClient::new().unwrap());
+        let cache = &self.cache;
+        self.app
+            .get_or_init(|| AppClient::new(cache.clone()).unwrap());
         self.app.get_mut().ok_or(AutoClientError::NoClient.into())
     }
+
+    /// Picks the client with more API budget left for calls on `repo`.
+    ///
+    /// If both clients are exhausted, waits for the earliest reset of their budget.
+    async fn choose(&mut self, repo: Option<&GithubRepo>, prefer: Choice) -> Result<Choice> {
+        let pat = TokenClient::is_available().then(|| rate::budget(&Account::Pat));
+        let app = if AppClient::is_available() {
+            let account = match repo {
+                Some(repo) => self.app_get_or_init()?.known_account(repo),
+                None => Some(Account::App),
+            };
+            let budget = account.and_then(|account| rate::budget(&account));
+            // The limit of an installation that is not known yet is (at least) the one of the app
+            Some((account.unwrap_or(Account::App), budget))
+        } else {
+            None
+        };
+
+        let (choice, rate) = match (pat, app) {
+            (None, None) => return Err(AutoClientError::NoClient.into()),
+            (Some(pat), None) => (Choice::Pat, pat),
+            (None, Some((_, app))) => (Choice::App, app),
+            (Some(pat), Some((app_account, app))) => {
+                let (pat_left, app_left) = (
+                    remaining(&pat, &Account::Pat),
+                    remaining(&app, &app_account),
+                );
+                let pat_first = match pat_left.cmp(&app_left) {
+                    std::cmp::Ordering::Greater => true,
+                    std::cmp::Ordering::Less => false,
+                    // both exhausted, take the one that resets first
+                    std::cmp::Ordering::Equal if pat_left == 0 => {
+                        pat.as_ref().map(|rate| rate.reset) <= app.as_ref().map(|rate| rate.reset)
+                    }
+                    std::cmp::Ordering::Equal => prefer == Choice::Pat,
+                };
+                if pat_first {
+                    (Choice::Pat, pat)
+                } else {
+                    (Choice::App, app)
+                }
+            }
+        };
+
+        if let Some(rate) = rate.filter(|rate| rate.remaining == 0) {
+            let wait = (rate.reset as i64 - now().timestamp()).max(0) as u64;
+            if wait > MAX_RATE_LIMIT_WAIT {
+                return Err(AutoClientError::RateLimited(wait).into());
+            }
+            tracing::warn!("API rate limit exhausted, waiting {wait}s for reset");
+            Delay::from(Duration::from_secs(wait)).await;
+        }
+        Ok(choice)
+    }
+
+    /// Logins of all accounts the bot may post as.
+    pub async fn logins(&mut self) -> Vec<String> {
+        let mut logins = Vec::new();
+        if TokenClient::is_available() {
+            match self.token.login().await {
+                Ok(login) => logins.push(login),
+                Err(error) => tracing::warn!("Cannot resolve PAT login: {error:?}"),
+            }
+        }
+        if AppClient::is_available() {
+            match self.app_get_or_init() {
+                Ok(app_cli) => match app_cli.login().await {
+                    Ok(login) => logins.push(login),
+                    Err(error) => tracing::warn!("Cannot resolve app login: {error:?}"),
+                },
+                Err(error) => tracing::warn!("Cannot create app client: {error:?}"),
+            }
+        }
+        logins
+    }
 }
 
 #[async_trait::async_trait(?Send)]
@@ -68,7 +168,7 @@ impl GitHubClient for AutoGitHubClient {
     }
 
     async fn get(&mut self, end: &str) -> anyhow::Result<reqwest::Response> {
-        pat_app!(self, get(end))
+        pat_app!(self, repo_of_endpoint(end).as_ref(), get(end))
     }
 
     async fn post<D: serde::Serialize + Sized>(
@@ -76,7 +176,7 @@ impl GitHubClient for AutoGitHubClient {
         end: &str,
         data: &D,
     ) -> anyhow::Result<reqwest::Response> {
-        pat_app!(self, post(end, data))
+        pat_app!(self, repo_of_endpoint(end).as_ref(), post(end, data))
     }
 
     async fn patch<D: serde::Serialize + Sized>(
@@ -84,7 +184,28 @@ impl GitHubClient for AutoGitHubClient {
         end: &str,
         data: &D,
     ) -> anyhow::Result<reqwest::Response> {
-        pat_app!(self, patch(end, data))
+        pat_app!(self, repo_of_endpoint(end).as_ref(), patch(end, data))
+    }
+
+    async fn delete(&mut self, end: &str) -> anyhow::Result<reqwest::Response> {
+        pat_app!(self, repo_of_endpoint(end).as_ref(), delete(end))
+    }
+
+    async fn post_graphql<D: serde::Serialize + Sized>(
+        &mut self,
+        repo: &GithubRepo,
+        body: &D,
+        read_only: bool,
+    ) -> anyhow::Result<reqwest::Response> {
+        app_pat!(self, Some(repo), post_graphql(repo, body, read_only))
+    }
+
+    async fn login(&mut self) -> anyhow::Result<String> {
+        pat_app!(self, None, login())
+    }
+
+    fn cache(&self) -> &Cache {
+        &self.cache
     }
 
     /// Post a comment to the pull request with the given number.
@@ -94,7 +215,7 @@ impl GitHubClient for AutoGitHubClient {
         pr: PullRequestNumber,
         text: &str,
     ) -> anyhow::Result<()> {
-        pat_app!(self, post_comment(repo, pr, text))
+        pat_app!(self, Some(repo), post_comment(repo, pr, text))
     }
 
     async fn get_pull_request(
@@ -102,7 +223,7 @@ impl GitHubClient for AutoGitHubClient {
         repo: &GithubRepo,
         pull_number: PullRequestNumber,
     ) -> Result<PullRequest> {
-        app_pat!(self, get_pull_request(repo, pull_number))
+        app_pat!(self, Some(repo), get_pull_request(repo, pull_number))
     }
 
     /// Set the given branch to a commit with the given `sha`.
@@ -115,7 +236,7 @@ impl GitHubClient for AutoGitHubClient {
         branch: &str,
         sha: &CommitSha,
     ) -> anyhow::Result<()> {
-        app_pat!(self, set_branch_to_sha(repo, branch, sha))
+        app_pat!(self, Some(repo), set_branch_to_sha(repo, branch, sha))
     }
 
     async fn create_branch(
@@ -124,7 +245,7 @@ impl GitHubClient for AutoGitHubClient {
         refs: &Reference,
         sha: &CommitSha,
     ) -> Result<()> {
-        app_pat!(self, create_branch(repo, refs, sha))
+        app_pat!(self, Some(repo), create_branch(repo, refs, sha))
     }
 
     /// Force update the branch with the given `branch_name` to the given `sha`.
@@ -134,7 +255,7 @@ impl GitHubClient for AutoGitHubClient {
         refs: &Reference,
         sha: &CommitSha,
     ) -> Result<()> {
-        app_pat!(self, update_branch(repo, refs, sha))
+        app_pat!(self, Some(repo), update_branch(repo, refs, sha))
     }
 
     /// Creates a merge commit on the given repository.
@@ -147,7 +268,28 @@ impl GitHubClient for AutoGitHubClient {
         head: &CommitSha,
         commit_message: &str,
     ) -> Result<CommitSha> {
-        app_pat!(self, merge_branches(repo, base, head, commit_message))
+        app_pat!(
+            self,
+            Some(repo),
+            merge_branches(repo, base, head, commit_message)
+        )
+    }
+
+    /// Creates a commit of the `tree` that is not referenced by any branch.
+    /// Returns the SHA of the commit.
+    async fn create_commit(
+        &mut self,
+        repo: &GithubRepo,
+        message: &str,
+        tree: &str,
+        parents: &[CommitSha],
+        author: Option<&CommitAuthor>,
+    ) -> Result<CommitSha> {
+        app_pat!(
+            self,
+            Some(repo),
+            create_commit(repo, message, tree, parents, author)
+        )
     }
 
     /// Find all check suites attached to the given commit and branch.
@@ -156,11 +298,32 @@ impl GitHubClient for AutoGitHubClient {
         branch: &str,
         sha: &CommitSha,
     ) -> anyhow::Result<Vec<CheckSuite>> {
-        app_pat!(self, get_check_suites_for_commit(branch, sha))
+        app_pat!(self, None, get_check_suites_for_commit(branch, sha))
+    }
+
+    /// Check runs can only be created by apps, so this never falls back to the PAT.
+    async fn create_check_run(
+        &mut self,
+        repo: &GithubRepo,
+        sha: &CommitSha,
+        name: &str,
+        conclusion: &str,
+        output: &CheckRunOutput,
+    ) -> anyhow::Result<()> {
+        if !AppClient::is_available() {
+            return Err(AutoClientError::NoClient.into());
+        }
+        self.app_get_or_init()?
+            .create_check_run(repo, sha, name, conclusion, output)
+            .await
     }
 
     /// Cancels Github Actions workflows.
-    async fn cancel_workflows(&mut self, run_ids: Vec<RunId>) -> anyhow::Result<()> {
-        app_pat!(self, cancel_workflows(run_ids))
+    async fn cancel_workflows(
+        &mut self,
+        repo: &GithubRepo,
+        run_ids: Vec<RunId>,
+    ) -> anyhow::Result<()> {
+        app_pat!(self, Some(repo), cancel_workflows(repo, run_ids))
     }
 }
@@ -1,16 +1,26 @@
+use crate::github::graphql::Operation;
 use anyhow::{Context, Result};
 use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
 use thiserror::Error;
 
 use super::misc::{CheckSuite, Reference};
 use super::{CommitSha, GithubRepo, PullRequest, PullRequestNumber};
+use crate::cache::Cache;
 use crate::github::misc::github_pr_to_pr;
-use crate::models::RunId;
+use crate::models::checks::{CheckRun, CheckRunOutput, ListCheckRuns};
+use crate::models::pulls::{FileDiff, Review};
+use crate::models::repos::{BranchRule, CommitAuthor, Content, Object, Ref, RepoCommit};
+use crate::models::workflows::Run;
+use crate::models::{Repository, RunId};
+use crate::permissions::RepoPermission;
 mod app;
 mod auto;
+mod rate;
+mod retry;
 mod token;
 pub use app::AppClient;
-pub use auto::AutoGitHubClient;
+pub use auto::{AutoClientError, AutoGitHubClient};
 pub use token::TokenClient;
 
 /// Provides functionality for working with a (authorized) client.
@@ -29,6 +39,59 @@ pub trait GitHubClient {
         end: &str,
         data: &D,
     ) -> Result<reqwest::Response>;
+    async fn delete(&mut self, end: &str) -> Result<reqwest::Response>;
+
+    /// Login of the account this client acts as (used to recognize own comments).
+    async fn login(&mut self) -> Result<String>;
+
+    /// Cache shared across webhook requests.
+    fn cache(&self) -> &Cache;
+
+    /// Sends a GraphQL request concerning the repository.
+    ///
+    /// Clients that authenticate per repository override this to pick the right token,
+    /// clients that retry requests retry it only if it is `read_only` (a query).
+    async fn post_graphql<D: serde::Serialize + Sized>(
+        &mut self,
+        _repo: &GithubRepo,
+        body: &D,
+        _read_only: bool,
+    ) -> Result<reqwest::Response> {
+        self.post("/graphql", body).await
+    }
+
+    /// Runs a typed GraphQL query (see [`crate::github::graphql`]) concerning the repository.
+    // Documentation: https://docs.github.com/en/graphql/guides/forming-calls-with-graphql
+    async fn graphql<Q: Operation>(
+        &mut self,
+        repo: &GithubRepo,
+        variables: Q::Variables,
+    ) -> Result<Q::ResponseData> {
+        let body = Q::build_query(variables);
+        let res = self
+            .post_graphql(repo, &body, Q::READ_ONLY)
+            .await
+            .with_context(|| format!("Cannot run {} on {repo}", body.operation_name))?;
+        if !res.status().is_success() {
+            return Err(anyhow::Error::new(StatusError(res.status()))
+                .context(format!("Body {:#?}", res.text().await)));
+        }
+        let response: graphql_client::Response<Q::ResponseData> = res
+            .json()
+            .await
+            .with_context(|| format!("Could not parse {} response", body.operation_name))?;
+        if let Some(errors) = response.errors.filter(|errors| !errors.is_empty()) {
+            let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+            return Err(anyhow::anyhow!(
+                "{} failed: {}",
+                body.operation_name,
+                errors.join("; ")
+            ));
+        }
+        response
+            .data
+            .with_context(|| format!("{} returned no data", body.operation_name))
+    }
 
     /// Post a comment to the pull request with the given number.
     async fn post_comment(
@@ -47,7 +110,206 @@ pub trait GitHubClient {
             .await
             .with_context(|| format!("Cannot post comment to {}", pr))?;
         if !res.status().is_success() {
-            return Err(anyhow::anyhow!("Got {}", res.status())
+            return Err(anyhow::Error::new(StatusError(res.status()))
+                .context(format!("Body {:#?}", res.text().await)));
+        }
+        Ok(())
+    }
+
+    /// Open a new issue in the repository.
+    async fn create_issue(&mut self, repo: &GithubRepo, title: &str, body: &str) -> Result<()> {
+        let res = self
+            .post(
+                &format!("/repos/{repo}/issues"),
+                &serde_json::json!({
+                    "title": title,
+                    "body": body,
+                }),
+            )
+            .await
+            .with_context(|| format!("Cannot create issue in {repo}"))?;
+        if !res.status().is_success() {
+            return Err(anyhow::Error::new(StatusError(res.status()))
+                .context(format!("Body {:#?}", res.text().await)));
+        }
+        Ok(())
+    }
+
+    async fn get_repository(&mut self, repo: &GithubRepo) -> Result<Repository> {
+        let res = self
+            .get(&format!("/repos/{repo}"))
+            .await
+            .with_context(|| format!("Could not get repository {repo}"))?;
+        check_status(res)
+            .await
+            .with_context(|| format!("Could not get repository {repo}"))?
+            .json()
+            .await
+            .with_context(|| format!("Could not parse repository {repo}"))
+    }
+
+    /// Content of a file on `reference` (branch or commit SHA), `None` if the file does not exist.
+    // Documentation: https://docs.github.com/en/rest/repos/contents?apiVersion=2022-11-28#get-repository-content
+    async fn get_file_content(
+        &mut self,
+        repo: &GithubRepo,
+        path: &str,
+        reference: &str,
+    ) -> Result<Option<String>> {
+        let res = self
+            .get(&format!("/repos/{repo}/contents/{path}?ref={reference}"))
+            .await
+            .with_context(|| format!("Could not get {path} of {repo}@{reference}"))?;
+        match res.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if !status.is_success() => Err(anyhow::Error::new(StatusError(status))
+                .context(format!("Body {:#?}", res.text().await))),
+            _ => {
+                let content: Content = res
+                    .json()
+                    .await
+                    .with_context(|| format!("Could not parse {path} of {repo}@{reference}"))?;
+                Ok(content.decoded_content())
+            }
+        }
+    }
+
+    /// Rules from rulesets that are active on the given branch.
+    // Documentation: https://docs.github.com/en/rest/repos/rules?apiVersion=2022-11-28#get-rules-for-a-branch
+    async fn get_branch_rules(
+        &mut self,
+        repo: &GithubRepo,
+        branch: &str,
+    ) -> Result<Vec<BranchRule>> {
+        let res = self
+            .get(&format!("/repos/{repo}/rules/branches/{branch}"))
+            .await
+            .with_context(|| format!("Could not get rules of {repo}/{branch}"))?;
+        check_status(res)
+            .await
+            .with_context(|| format!("Could not get rules of {repo}/{branch}"))?
+            .json()
+            .await
+            .with_context(|| format!("Could not parse rules of {repo}/{branch}"))
+    }
+
+    /// SHA of the commit the branch currently points to.
+    async fn get_branch_sha(&mut self, repo: &GithubRepo, branch: &str) -> Result<CommitSha> {
+        let res = self
+            .get(&format!("/repos/{repo}/git/ref/heads/{branch}"))
+            .await
+            .with_context(|| format!("Could not get branch {repo}/{branch}"))?;
+        let reference: Ref = check_status(res)
+            .await
+            .with_context(|| format!("Could not get branch {repo}/{branch}"))?
+            .json()
+            .await
+            .with_context(|| format!("Could not parse branch {repo}/{branch}"))?;
+        match reference.object {
+            Object::Commit { sha, .. } | Object::Tag { sha, .. } => Ok(sha.into()),
+        }
+    }
+
+    /// Whether the user is an active member of the team `org/team`.
+    // Documentation: https://docs.github.com/en/rest/teams/members?apiVersion=2022-11-28#get-team-membership-for-a-user
+    async fn is_team_member(&mut self, org: &str, team: &str, user: &str) -> Result<bool> {
+        let res = self
+            .get(&format!("/orgs/{org}/teams/{team}/memberships/{user}"))
+            .await
+            .with_context(|| format!("Could not get membership of {user} in {org}/{team}"))?;
+        match res.status() {
+            StatusCode::NOT_FOUND => Ok(false),
+            status if !status.is_success() => Err(anyhow::Error::new(StatusError(status))
+                .context(format!("Body {:#?}", res.text().await))),
+            _ => {
+                let membership: TeamMembership = res
+                    .json()
+                    .await
+                    .context("Could not parse team membership")?;
+                Ok(membership.state == "active")
+            }
+        }
+    }
+
+    /// Permission of the user on the repository, `None` if the user is not a collaborator.
+    // Documentation: https://docs.github.com/en/rest/collaborators/collaborators?apiVersion=2022-11-28#get-repository-permissions-for-a-user
+    async fn get_collaborator_permission(
+        &mut self,
+        repo: &GithubRepo,
+        user: &str,
+    ) -> Result<Option<RepoPermission>> {
+        let res = self
+            .get(&format!("/repos/{repo}/collaborators/{user}/permission"))
+            .await
+            .with_context(|| format!("Could not get permission of {user} on {repo}"))?;
+        match res.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if !status.is_success() => Err(anyhow::Error::new(StatusError(status))
+                .context(format!("Body {:#?}", res.text().await))),
+            _ => {
+                let permission: CollaboratorPermission = res
+                    .json()
+                    .await
+                    .context("Could not parse collaborator permission")?;
+                // `role_name` distinguishes triage and maintain, but may be a custom role
+                Ok(
+                    serde_json::from_value(serde_json::Value::String(permission.role_name))
+                        .or_else(|_| {
+                            serde_json::from_value(serde_json::Value::String(permission.permission))
+                        })
+                        .ok(),
+                )
+            }
+        }
+    }
+
+    /// Latest check runs of the given commit.
+    // Documentation: https://docs.github.com/en/rest/checks/runs?apiVersion=2022-11-28#list-check-runs-for-a-git-reference
+    async fn get_check_runs(
+        &mut self,
+        repo: &GithubRepo,
+        sha: &CommitSha,
+    ) -> Result<Vec<CheckRun>> {
+        let res = self
+            .get(&format!(
+                "/repos/{repo}/commits/{sha}/check-runs?filter=latest&per_page=100"
+            ))
+            .await
+            .with_context(|| format!("Could not get check runs of {repo}@{sha}"))?;
+        let runs: ListCheckRuns = check_status(res)
+            .await
+            .with_context(|| format!("Could not get check runs of {repo}@{sha}"))?
+            .json()
+            .await
+            .with_context(|| format!("Could not parse check runs of {repo}@{sha}"))?;
+        Ok(runs.check_runs)
+    }
+
+    /// Creates a completed check run on the commit.
+    // Documentation: https://docs.github.com/en/rest/checks/runs?apiVersion=2022-11-28#create-a-check-run
+    async fn create_check_run(
+        &mut self,
+        repo: &GithubRepo,
+        sha: &CommitSha,
+        name: &str,
+        conclusion: &str,
+        output: &CheckRunOutput,
+    ) -> Result<()> {
+        let res = self
+            .post(
+                &format!("/repos/{repo}/check-runs"),
+                &serde_json::json!({
+                    "name": name,
+                    "head_sha": sha.as_ref(),
+                    "status": "completed",
+                    "conclusion": conclusion,
+                    "output": output,
+                }),
+            )
+            .await
+            .with_context(|| format!("Cannot create check run {name} on {repo}@{sha}"))?;
+        if !res.status().is_success() {
+            return Err(anyhow::Error::new(StatusError(res.status()))
                 .context(format!("Body {:#?}", res.text().await)));
         }
         Ok(())
@@ -73,6 +335,69 @@ pub trait GitHubClient {
         Ok(github_pr_to_pr(pr))
     }
 
+    /// Items of all pages of a list endpoint (`end` without a query), up to `max_pages` pages.
+    // Documentation: https://docs.github.com/en/rest/using-the-rest-api/using-pagination-in-the-rest-api
+    async fn get_paginated<T: DeserializeOwned>(
+        &mut self,
+        end: &str,
+        max_pages: usize,
+    ) -> Result<Vec<T>> {
+        const PER_PAGE: usize = 100;
+        let mut items = Vec::new();
+        for page in 1..=max_pages {
+            let res = self
+                .get(&format!("{end}?per_page={PER_PAGE}&page={page}"))
+                .await?;
+            let batch: Vec<T> = check_status(res)
+                .await?
+                .json()
+                .await
+                .with_context(|| format!("Could not parse page {page} of {end}"))?;
+            let last = batch.len() < PER_PAGE;
+            items.extend(batch);
+            if last {
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    /// Reviews of the pull request, in chronological order.
+    // Documentation: https://docs.github.com/en/rest/pulls/reviews?apiVersion=2022-11-28#list-reviews-for-a-pull-request
+    async fn get_pull_request_reviews(
+        &mut self,
+        repo: &GithubRepo,
+        pr: PullRequestNumber,
+    ) -> Result<Vec<Review>> {
+        self.get_paginated(&format!("/repos/{repo}/pulls/{pr}/reviews"), 30)
+            .await
+            .with_context(|| format!("Could not get reviews of {repo}#{pr}"))
+    }
+
+    /// Files changed by the pull request (GitHub lists at most 3000).
+    // Documentation: https://docs.github.com/en/rest/pulls/pulls?apiVersion=2022-11-28#list-pull-requests-files
+    async fn get_pull_request_files(
+        &mut self,
+        repo: &GithubRepo,
+        pr: PullRequestNumber,
+    ) -> Result<Vec<FileDiff>> {
+        self.get_paginated(&format!("/repos/{repo}/pulls/{pr}/files"), 30)
+            .await
+            .with_context(|| format!("Could not get files of {repo}#{pr}"))
+    }
+
+    /// Commits of the pull request (GitHub lists at most 250).
+    // Documentation: https://docs.github.com/en/rest/pulls/pulls?apiVersion=2022-11-28#list-commits-on-a-pull-request
+    async fn get_pull_request_commits(
+        &mut self,
+        repo: &GithubRepo,
+        pr: PullRequestNumber,
+    ) -> Result<Vec<RepoCommit>> {
+        self.get_paginated(&format!("/repos/{repo}/pulls/{pr}/commits"), 3)
+            .await
+            .with_context(|| format!("Could not get commits of {repo}#{pr}"))
+    }
+
     /// Set the given branch to a commit with the given `sha`.
     ///
     /// Forcefully updates the branch to the given commit `sha`.
@@ -90,10 +415,7 @@ pub trait GitHubClient {
             Err(error) => match error.downcast_ref() {
                 Some(BranchUpdateError::BranchNotFound(_)) => {
                     // Branch does not exist yet, try to create it
-                    match self.create_branch(repo, &branch_ref, sha).await {
-                        Ok(_) => Ok(()),
-                        Err(err) => Err(BranchUpdateError::Custom(err).into()),
-                    }
+                    self.create_branch(repo, &branch_ref, sha).await
                 }
                 _ => Err(error),
             },
@@ -116,8 +438,11 @@ pub trait GitHubClient {
             )
             .await?;
 
-        if !res.status().is_success() {
-            return Err(anyhow::anyhow!("Got {}", res.status()));
+        let status = res.status();
+        if !status.is_success() {
+            let text = res.text().await.unwrap_or_default();
+            tracing::trace!("Creating branch response: status={status}, text={text:?}");
+            return Err(BranchUpdateError::from_response(refs.internal(), status, &text).into());
         }
         /*repo.client
         .repos(repo.repo_name.owner(), repo.repo_name.name())
@@ -145,15 +470,12 @@ pub trait GitHubClient {
             .await?;
 
         let status = res.status();
-        tracing::trace!(
-            "Updating branch response: status={}, text={:?}",
-            status,
-            res.text().await
-        );
+        let text = res.text().await.unwrap_or_default();
+        tracing::trace!("Updating branch response: status={status}, text={text:?}");
 
         match status {
             StatusCode::OK => Ok(()),
-            _ => Err(BranchUpdateError::BranchNotFound(refs.internal().to_owned()).into()),
+            _ => Err(BranchUpdateError::from_response(refs.internal(), status, &text).into()),
         }
     }
 
@@ -213,6 +535,68 @@ pub trait GitHubClient {
         }
     }
 
+    /// Best common ancestor of `base` and `head`.
+    // Documentation: https://docs.github.com/en/rest/commits/commits?apiVersion=2022-11-28#compare-two-commits
+    async fn get_merge_base(
+        &mut self,
+        repo: &GithubRepo,
+        base: &CommitSha,
+        head: &CommitSha,
+    ) -> Result<CommitSha> {
+        let comparison: Comparison = self
+            .get(&format!("/repos/{repo}/compare/{base}...{head}?per_page=1"))
+            .await
+            .with_context(|| format!("Could not compare {base} and {head}"))?
+            .json()
+            .await
+            .with_context(|| format!("Could not parse comparison of {base} and {head}"))?;
+        Ok(comparison.merge_base_commit.sha.into())
+    }
+
+    /// Tree of the commit.
+    // Documentation: https://docs.github.com/en/rest/git/commits?apiVersion=2022-11-28#get-a-commit-object
+    async fn get_commit_tree(&mut self, repo: &GithubRepo, sha: &CommitSha) -> Result<String> {
+        let commit: GitCommit = self
+            .get(&format!("/repos/{repo}/git/commits/{sha}"))
+            .await
+            .with_context(|| format!("Could not get commit {sha}"))?
+            .json()
+            .await
+            .with_context(|| format!("Could not parse commit {sha}"))?;
+        Ok(commit.tree.sha)
+    }
+
+    /// Creates a commit of the `tree` that is not referenced by any branch.
+    /// Returns the SHA of the commit.
+    // Documentation: https://docs.github.com/en/rest/git/commits?apiVersion=2022-11-28#create-a-commit
+    async fn create_commit(
+        &mut self,
+        repo: &GithubRepo,
+        message: &str,
+        tree: &str,
+        parents: &[CommitSha],
+        author: Option<&CommitAuthor>,
+    ) -> Result<CommitSha> {
+        let mut request = serde_json::json!({
+            "message": message,
+            "tree": tree,
+            "parents": parents.iter().map(AsRef::as_ref).collect::<Vec<&str>>(),
+        });
+        if let Some(author) = author {
+            request["author"] = serde_json::json!(author);
+        }
+        let res = self
+            .post(&format!("/repos/{repo}/git/commits"), &request)
+            .await
+            .context("Could not create commit")?;
+        if res.status() != StatusCode::CREATED {
+            return Err(anyhow::Error::new(StatusError(res.status()))
+                .context(format!("Body {:#?}", res.text().await)));
+        }
+        let commit: GitCommit = res.json().await.context("Could not parse created commit")?;
+        Ok(commit.sha.into())
+    }
+
     /// Find all check suites attached to the given commit and branch.
     async fn get_check_suites_for_commit(
         &mut self,
@@ -273,34 +657,116 @@ pub trait GitHubClient {
         Ok(suites)*/
     }
 
-    /// Cancels Github Actions workflows.
-    async fn cancel_workflows(&mut self, run_ids: Vec<RunId>) -> Result<()> {
-        todo!()
-        /*let actions = reqwest::Client::new().actions();
-
-        // Cancel all workflows in parallel
-        futures::future::join_all(run_ids.into_iter().map(|run_id| {
-            actions.cancel_workflow_run(self.repo_name.owner(), self.repo_name.name(), run_id)
-        }))
-        .await
-        .into_iter()
-        .collect::<Result<Vec<_>, _>>()?;
+    /// Github Actions workflow runs of the given commit.
+    // Documentation: https://docs.github.com/en/rest/actions/workflow-runs?apiVersion=2022-11-28#list-workflow-runs-for-a-repository
+    async fn get_workflow_runs(&mut self, repo: &GithubRepo, sha: &CommitSha) -> Result<Vec<Run>> {
+        let res = self
+            .get(&format!(
+                "/repos/{repo}/actions/runs?head_sha={sha}&per_page=100"
+            ))
+            .await
+            .with_context(|| format!("Could not get workflow runs of {repo}@{sha}"))?;
+        let runs: WorkflowRuns = check_status(res)
+            .await
+            .with_context(|| format!("Could not get workflow runs of {repo}@{sha}"))?
+            .json()
+            .await
+            .with_context(|| format!("Could not parse workflow runs of {repo}@{sha}"))?;
+        Ok(runs.workflow_runs)
+    }
 
-        Ok(())*/
+    /// Cancels Github Actions workflows.
+    // Documentation: https://docs.github.com/en/rest/actions/workflow-runs?apiVersion=2022-11-28#cancel-a-workflow-run
+    async fn cancel_workflows(&mut self, repo: &GithubRepo, run_ids: Vec<RunId>) -> Result<()> {
+        for run_id in run_ids {
+            let res = self
+                .post(
+                    &format!("/repos/{repo}/actions/runs/{run_id}/cancel"),
+                    &serde_json::json!({}),
+                )
+                .await
+                .with_context(|| format!("Cannot cancel workflow run {run_id}"))?;
+            match res.status() {
+                // the run has already finished
+                StatusCode::CONFLICT => {
+                    tracing::debug!("Workflow run {run_id} cannot be cancelled anymore")
+                }
+                status if !status.is_success() => {
+                    return Err(anyhow::Error::new(StatusError(status))
+                        .context(format!("Cannot cancel workflow run {run_id}")));
+                }
+                _ => tracing::info!("Workflow run {run_id} cancelled"),
+            }
+        }
+        Ok(())
     }
 
-    // IDK
-    /*
     /// Add a set of labels to a PR.
-    async fn add_labels(&mut self, pr: PullRequestNumber, labels: &[String]) -> anyhow::Result<()>;
+    // Documentation: https://docs.github.com/en/rest/issues/labels?apiVersion=2022-11-28#add-labels-to-an-issue
+    async fn add_labels(
+        &mut self,
+        repo: &GithubRepo,
+        pr: PullRequestNumber,
+        labels: &[String],
+    ) -> Result<()> {
+        let res = self
+            .post(
+                &format!("/repos/{repo}/issues/{pr}/labels"),
+                &serde_json::json!({ "labels": labels }),
+            )
+            .await
+            .with_context(|| format!("Cannot add labels to {repo}#{pr}"))?;
+        if !res.status().is_success() {
+            return Err(anyhow::Error::new(StatusError(res.status()))
+                .context(format!("Body {:#?}", res.text().await)));
+        }
+        Ok(())
+    }
 
-    /// Remove a set of labels from a PR.
+    /// Remove a set of labels from a PR, labels that are not set are ignored.
+    // Documentation: https://docs.github.com/en/rest/issues/labels?apiVersion=2022-11-28#remove-a-label-from-an-issue
     async fn remove_labels(
         &mut self,
+        repo: &GithubRepo,
         pr: PullRequestNumber,
         labels: &[String],
-    ) -> anyhow::Result<()>;
-    */
+    ) -> Result<()> {
+        for label in labels {
+            let res = self
+                .delete(&format!(
+                    "/repos/{repo}/issues/{pr}/labels/{}",
+                    encode_path_segment(label)
+                ))
+                .await
+                .with_context(|| format!("Cannot remove label {label} from {repo}#{pr}"))?;
+            match res.status() {
+                StatusCode::NOT_FOUND => {
+                    tracing::debug!("Label {label} is not set on {repo}#{pr}")
+                }
+                status if !status.is_success() => {
+                    return Err(anyhow::Error::new(StatusError(status))
+                        .context(format!("Cannot remove label {label} from {repo}#{pr}")));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Unexpected status of a GitHub response.
+#[derive(Error, Debug)]
+#[error("Got {0}")]
+pub struct StatusError(pub StatusCode);
+
+/// Fails with a [`StatusError`] (and the body) unless the response is successful,
+/// so that API errors are not reported as unparsable responses.
+async fn check_status(res: reqwest::Response) -> Result<reqwest::Response> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+    Err(anyhow::Error::new(StatusError(status)).context(format!("Body {:#?}", res.text().await)))
 }
 
 #[derive(Error, Debug)]
@@ -329,10 +795,157 @@ struct MergeResponse {
     sha: String,
 }
 
+#[derive(serde::Deserialize)]
+struct Comparison {
+    merge_base_commit: GitObject,
+}
+
+#[derive(serde::Deserialize)]
+struct GitObject {
+    sha: String,
+}
+
+#[derive(serde::Deserialize)]
+struct GitCommit {
+    sha: String,
+    tree: GitObject,
+}
+
+#[derive(serde::Deserialize)]
+struct TeamMembership {
+    state: String,
+}
+
+#[derive(serde::Deserialize)]
+struct CollaboratorPermission {
+    permission: String,
+    #[serde(default)]
+    role_name: String,
+}
+
+#[derive(serde::Deserialize)]
+struct WorkflowRuns {
+    workflow_runs: Vec<Run>,
+}
+
 #[derive(Error, Debug)]
 pub enum BranchUpdateError {
     #[error("Branch {0} was not found")]
     BranchNotFound(String),
+    #[error("Branch {branch} is protected: {reason}")]
+    Protected { branch: String, reason: String },
+    #[error("Not allowed to update branch {branch}: {reason}")]
+    Forbidden { branch: String, reason: String },
+    #[error("Update of branch {0} is not a fast forward")]
+    NotFastForward(String),
+    #[error("API rate limit exceeded")]
+    RateLimited,
     #[error("Unknown error: {0}")]
     Custom(#[from] anyhow::Error),
 }
+
+impl BranchUpdateError {
+    /// Classifies an error response of a ref endpoint by its status and message.
+    fn from_response(branch: &str, status: StatusCode, text: &str) -> Self {
+        let reason = serde_json::from_str::<ErrorResponse>(text)
+            .map(|error| error.message)
+            .unwrap_or_else(|_| text.to_owned());
+        let message = reason.to_lowercase();
+        let branch = branch.to_owned();
+        match status {
+            StatusCode::NOT_FOUND => Self::BranchNotFound(branch),
+            StatusCode::TOO_MANY_REQUESTS => Self::RateLimited,
+            StatusCode::FORBIDDEN if message.contains("rate limit") => Self::RateLimited,
+            StatusCode::FORBIDDEN => Self::Forbidden { branch, reason },
+            StatusCode::UNPROCESSABLE_ENTITY if message.contains("does not exist") => {
+                Self::BranchNotFound(branch)
+            }
+            StatusCode::UNPROCESSABLE_ENTITY if message.contains("protected branch") => {
+                Self::Protected { branch, reason }
+            }
+            StatusCode::UNPROCESSABLE_ENTITY if message.contains("fast forward") => {
+                Self::NotFastForward(branch)
+            }
+            _ => Self::Custom(anyhow::anyhow!("Got {status}: {reason}")),
+        }
+    }
+}
+
+/// Percent-encodes text (e.g. a label name) to be used as a single segment of an endpoint path.
+fn encode_path_segment(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(byte).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+/// Body of GitHub API error responses.
+#[derive(serde::Deserialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[test]
+fn branch_update_error_classification() {
+    let classify = |status, message: &str| {
+        BranchUpdateError::from_response(
+            "try",
+            status,
+            &serde_json::json!({ "message": message }).to_string(),
+        )
+    };
+    assert!(matches!(
+        classify(StatusCode::UNPROCESSABLE_ENTITY, "Reference does not exist"),
+        BranchUpdateError::BranchNotFound(_)
+    ));
+    assert!(matches!(
+        classify(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Protected branch update failed for refs/heads/try."
+        ),
+        BranchUpdateError::Protected { .. }
+    ));
+    assert!(matches!(
+        classify(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Update is not a fast forward"
+        ),
+        BranchUpdateError::NotFastForward(_)
+    ));
+    assert!(matches!(
+        classify(
+            StatusCode::FORBIDDEN,
+            "Resource not accessible by integration"
+        ),
+        BranchUpdateError::Forbidden { .. }
+    ));
+    assert!(matches!(
+        classify(
+            StatusCode::FORBIDDEN,
+            "API rate limit exceeded for installation"
+        ),
+        BranchUpdateError::RateLimited
+    ));
+    assert!(matches!(
+        BranchUpdateError::from_response("try", StatusCode::BAD_GATEWAY, "oops"),
+        BranchUpdateError::Custom(_)
+    ));
+}
+
+#[test]
+fn path_segment_encoding() {
+    assert_eq!(
+        encode_path_segment("S-awaiting-review"),
+        "S-awaiting-review"
+    );
+    assert_eq!(
+        encode_path_segment("has merge conflict"),
+        "has%20merge%20conflict"
+    );
+    assert_eq!(encode_path_segment("a/b?c#d"), "a%2Fb%3Fc%23d");
+    assert_eq!(encode_path_segment("é"), "%C3%A9");
+}
@@ -0,0 +1,87 @@
+//! Tracks the remaining API budget of each account from the `X-RateLimit-*`
+//! headers of every response.
+//!
+//! Budgets live in the memory of the isolate, so they are shared by all webhooks
+//! handled by the same worker instance.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+use crate::models::{InstallationId, Rate};
+use crate::utils::time::now;
+
+/// Account whose budget is spent by a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Account {
+    /// Personal access token
+    Pat,
+    /// The app itself (JWT)
+    App,
+    /// Access token of an app installation
+    Installation(InstallationId),
+}
+
+/// Hourly limit of a personal access token.
+// Documentation: https://docs.github.com/en/rest/using-the-rest-api/rate-limits-for-the-rest-api
+const PAT_LIMIT: usize = 5000;
+/// Hourly limit of an app (and of its installations at least, they may get more).
+const APP_LIMIT: usize = 5000;
+
+impl Account {
+    /// Hourly limit of the account, until its budget is known from a response.
+    pub fn default_limit(&self) -> usize {
+        match self {
+            Account::Pat => PAT_LIMIT,
+            Account::App | Account::Installation(_) => APP_LIMIT,
+        }
+    }
+}
+
+fn rates() -> &'static Mutex<HashMap<Account, Rate>> {
+    static RATES: OnceLock<Mutex<HashMap<Account, Rate>>> = OnceLock::new();
+    RATES.get_or_init(Default::default)
+}
+
+fn header(res: &reqwest::Response, name: &str) -> Option<usize> {
+    res.headers().get(name)?.to_str().ok()?.parse().ok()
+}
+
+/// Records the budget reported by the response.
+///
+/// Only the REST (`core`) budget is tracked, GraphQL has a separate one.
+pub fn observe(account: Account, res: reqwest::Response) -> reqwest::Response {
+    let resource = res
+        .headers()
+        .get("x-ratelimit-resource")
+        .and_then(|resource| resource.to_str().ok());
+    if resource.is_some_and(|resource| resource != "core") {
+        return res;
+    }
+    if let (Some(limit), Some(remaining), Some(reset)) = (
+        header(&res, "x-ratelimit-limit"),
+        header(&res, "x-ratelimit-remaining"),
+        header(&res, "x-ratelimit-reset"),
+    ) {
+        let used = header(&res, "x-ratelimit-used").unwrap_or(limit.saturating_sub(remaining));
+        tracing::trace!("{account:?} has {remaining}/{limit} requests left");
+        if let Ok(mut rates) = rates().lock() {
+            rates.insert(
+                account,
+                Rate {
+                    limit,
+                    used,
+                    remaining,
+                    reset,
+                },
+            );
+        }
+    }
+    res
+}
+
+/// Known budget of the account, `None` if unknown or its window was already reset.
+pub fn budget(account: &Account) -> Option<Rate> {
+    let rates = rates().lock().ok()?;
+    let rate = rates.get(account)?;
+    (rate.reset as i64 > now().timestamp()).then(|| rate.clone())
+}
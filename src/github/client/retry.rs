@@ -0,0 +1,137 @@
+//! Retries of requests that failed for transient reasons.
+//!
+//! Network errors and server errors are retried with jittered exponential backoff,
+//! but only for idempotent requests, as a non-idempotent one may have already
+//! taken effect. Read-only requests sent with POST (GraphQL queries) opt in with
+//! [`send_idempotent`]. Secondary rate limits reject the request, so it is always safe
+//! to retry after the time given in `Retry-After`.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use worker::Delay;
+
+use super::rate::{self, Account};
+
+/// Attempts of a request, including the first one.
+const MAX_ATTEMPTS: u32 = 4;
+/// Backoff before the first retry (in milliseconds), doubled with each attempt.
+const BASE_BACKOFF: u64 = 500;
+/// Longest `Retry-After` (in seconds) worth waiting for.
+const MAX_RETRY_AFTER: u64 = 60;
+
+/// Whether repeating the request has the same effect as sending it once.
+fn is_idempotent(method: &Method) -> bool {
+    // PATCH is only used to set fields (e.g. force update a ref) which is idempotent
+    !matches!(*method, Method::POST)
+}
+
+/// Seconds to wait before retrying, if the response is a secondary rate limit.
+fn retry_after(res: &Response) -> Option<u64> {
+    if !matches!(
+        res.status(),
+        StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS
+    ) {
+        return None;
+    }
+    res.headers()
+        .get("retry-after")?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// Random delay up to the exponential backoff of the given attempt ("full jitter").
+fn backoff(attempt: u32) -> Duration {
+    let max = BASE_BACKOFF << attempt.saturating_sub(1);
+    Duration::from_millis((random() * max as f64) as u64)
+}
+
+/// Random number in `[0, 1)`.
+#[cfg(target_arch = "wasm32")]
+fn random() -> f64 {
+    // The clock of Workers only advances on I/O, so it cannot be used as a source
+    js_sys::Math::random()
+}
+
+/// Random number in `[0, 1)`, good enough for spreading retries in tests.
+#[cfg(not(target_arch = "wasm32"))]
+fn random() -> f64 {
+    f64::from(crate::utils::time::now().timestamp_subsec_nanos() % 1_000_000_000) / 1e9
+}
+
+/// Sends the request built by `request` for `method`, retrying on transient failures.
+///
+/// The request is rebuilt for every attempt, as it cannot be cloned in general.
+pub async fn send(
+    account: Account,
+    method: Method,
+    request: impl Fn(Method) -> RequestBuilder,
+) -> Result<Response> {
+    let idempotent = is_idempotent(&method);
+    send_with(account, method, idempotent, request).await
+}
+
+/// Like [`send`], for requests that are idempotent whatever their method.
+pub async fn send_idempotent(
+    account: Account,
+    method: Method,
+    request: impl Fn(Method) -> RequestBuilder,
+) -> Result<Response> {
+    send_with(account, method, true, request).await
+}
+
+async fn send_with(
+    account: Account,
+    method: Method,
+    idempotent: bool,
+    request: impl Fn(Method) -> RequestBuilder,
+) -> Result<Response> {
+    let mut attempt = 1;
+    loop {
+        let wait = match request(method.clone()).send().await {
+            Ok(res) => {
+                let res = rate::observe(account, res);
+                if attempt >= MAX_ATTEMPTS {
+                    return Ok(res);
+                }
+                match retry_after(&res) {
+                    Some(secs) if secs <= MAX_RETRY_AFTER => {
+                        tracing::warn!("Secondary rate limit hit, retrying in {secs}s");
+                        Duration::from_secs(secs)
+                    }
+                    Some(_) => return Ok(res),
+                    None if idempotent && res.status().is_server_error() => {
+                        tracing::warn!("Got {}, retrying (attempt {attempt})", res.status());
+                        backoff(attempt)
+                    }
+                    None => return Ok(res),
+                }
+            }
+            Err(error) if idempotent && attempt < MAX_ATTEMPTS => {
+                tracing::warn!("Request failed, retrying (attempt {attempt}): {error:?}");
+                backoff(attempt)
+            }
+            Err(error) => return Err(anyhow::anyhow!(error)),
+        };
+        Delay::from(wait).await;
+        attempt += 1;
+    }
+}
+
+#[test]
+fn idempotency() {
+    assert!(is_idempotent(&Method::GET));
+    assert!(is_idempotent(&Method::PATCH));
+    assert!(is_idempotent(&Method::PUT));
+    assert!(!is_idempotent(&Method::POST));
+}
+
+#[test]
+fn backoff_is_bounded() {
+    for attempt in 1..=MAX_ATTEMPTS {
+        assert!(backoff(attempt) <= Duration::from_millis(BASE_BACKOFF << (attempt - 1)));
+    }
+}
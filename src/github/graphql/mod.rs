@@ -0,0 +1,148 @@
+//! Typed GraphQL queries generated from the vendored `schema.graphql`.
+//!
+//! Run them with [`GitHubClient::graphql`](crate::github::client::GitHubClient::graphql).
+//! Queries are defined in the `.graphql` files next to this module,
+//! every operation tells whether it only reads with [`Operation::READ_ONLY`].
+#![allow(clippy::upper_case_acronyms)]
+
+use graphql_client::GraphQLQuery;
+
+// Custom scalars of the schema that are used by the queries
+type DateTime = String;
+type GitObjectID = String;
+
+/// A GraphQL operation that the bot runs.
+pub trait Operation: GraphQLQuery {
+    /// The operation is a query, so it can safely be retried (mutations are not idempotent).
+    const READ_ONLY: bool;
+}
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "schema.graphql",
+    query_path = "src/github/graphql/merge_queue.graphql",
+    response_derives = "Debug"
+)]
+pub struct EnqueuePullRequest;
+
+impl Operation for EnqueuePullRequest {
+    const READ_ONLY: bool = false;
+}
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "schema.graphql",
+    query_path = "src/github/graphql/merge_queue.graphql",
+    response_derives = "Debug"
+)]
+pub struct DequeuePullRequest;
+
+impl Operation for DequeuePullRequest {
+    const READ_ONLY: bool = false;
+}
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "schema.graphql",
+    query_path = "src/github/graphql/merge_queue.graphql",
+    response_derives = "Debug"
+)]
+pub struct MergeQueueEntries;
+
+impl Operation for MergeQueueEntries {
+    const READ_ONLY: bool = true;
+}
+
+/// Pull request with its commits, labels and review decision.
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "schema.graphql",
+    query_path = "src/github/graphql/pull_request.graphql",
+    response_derives = "Debug"
+)]
+pub struct PullRequestDetails;
+
+impl Operation for PullRequestDetails {
+    const READ_ONLY: bool = true;
+}
+
+/// Hides a comment (e.g. an outdated status comment of the bot).
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "schema.graphql",
+    query_path = "src/github/graphql/comment.graphql",
+    response_derives = "Debug"
+)]
+pub struct MinimizeComment;
+
+impl Operation for MinimizeComment {
+    const READ_ONLY: bool = false;
+}
+
+/// The documents contain several operations, so the kind is looked up by name.
+#[cfg(test)]
+fn is_query<Q: Operation>(variables: Q::Variables) -> bool {
+    let body = Q::build_query(variables);
+    let query = format!("query {}(", body.operation_name);
+    let mutation = format!("mutation {}(", body.operation_name);
+    assert!(body.query.contains(&query) || body.query.contains(&mutation));
+    body.query.contains(&query)
+}
+
+#[test]
+fn read_only_operations() {
+    assert_eq!(
+        is_query::<MergeQueueEntries>(merge_queue_entries::Variables {
+            owner: String::new(),
+            name: String::new(),
+            branch: None,
+        }),
+        MergeQueueEntries::READ_ONLY
+    );
+    assert_eq!(
+        is_query::<EnqueuePullRequest>(enqueue_pull_request::Variables {
+            pull_request_id: String::new(),
+            expected_head_oid: None,
+            jump: None,
+        }),
+        EnqueuePullRequest::READ_ONLY
+    );
+    assert_eq!(
+        is_query::<DequeuePullRequest>(dequeue_pull_request::Variables { id: String::new() }),
+        DequeuePullRequest::READ_ONLY
+    );
+    assert_eq!(
+        is_query::<PullRequestDetails>(pull_request_details::Variables {
+            owner: String::new(),
+            name: String::new(),
+            number: 0,
+        }),
+        PullRequestDetails::READ_ONLY
+    );
+    assert_eq!(
+        is_query::<MinimizeComment>(minimize_comment::Variables {
+            subject_id: String::new(),
+            classifier: minimize_comment::ReportedContentClassifiers::OUTDATED,
+        }),
+        MinimizeComment::READ_ONLY
+    );
+}
+
+#[test]
+fn enqueue_query() {
+    let body = EnqueuePullRequest::build_query(enqueue_pull_request::Variables {
+        pull_request_id: "PR_kwDO".to_string(),
+        expected_head_oid: Some("abc".to_string()),
+        jump: None,
+    });
+    assert_eq!(body.operation_name, "EnqueuePullRequest");
+    assert_eq!(
+        serde_json::to_value(&body.variables).unwrap(),
+        serde_json::json!({
+            "pullRequestId": "PR_kwDO",
+            "expectedHeadOid": "abc",
+            "jump": null,
+        })
+    );
+    assert!(body.query.contains("enqueuePullRequest"));
+}
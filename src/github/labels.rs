@@ -4,6 +4,9 @@ pub enum LabelTrigger {
     TryBuildStarted,
     TryBuildSucceeded,
     TryBuildFailed,
+    /// The PR cannot be merged into its base branch anymore, additions are reverted
+    /// once the conflict is resolved.
+    MergeConflict,
 }
 
 #[derive(Debug, Eq, PartialEq)]
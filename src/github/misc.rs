@@ -2,10 +2,10 @@ use chrono::{DateTime, Utc};
 
 use super::{Branch, PullRequest};
 use crate::github::PullRequestNumber;
-use crate::models::RunId;
+use crate::models::{IssueState, RunId};
 
 /// Status of a GitHub build.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
 pub enum BuildStatus {
     /// The build is still waiting for results.
     Pending,
@@ -20,15 +20,23 @@ pub enum BuildStatus {
 }
 
 /// Represents a single (merged) commit.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct BuildModel {
     pub repository: String,
     pub branch: String,
     pub commit_sha: String,
     pub status: BuildStatus,
     pub created_at: DateTime<Utc>,
+    /// Head of the pull request the build was started for.
+    #[serde(default)]
+    pub head_sha: Option<String>,
+    /// Whether the start of the build was already reported on the pull request.
+    #[serde(default)]
+    pub announced: bool,
 }
 
 /// Represents a pull request.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct PullRequestModel {
     pub repository: String,
     pub number: PullRequestNumber,
@@ -38,14 +46,14 @@ pub struct PullRequestModel {
 
 /// Describes whether a workflow is a Github Actions workflow or if it's a job from some external
 /// CI.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub enum WorkflowType {
     Github,
     External,
 }
 
 /// Status of a workflow.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub enum WorkflowStatus {
     /// Workflow is running.
     Pending,
@@ -119,6 +127,8 @@ pub fn github_pr_to_pr(pr: crate::models::pulls::PullRequest) -> PullRequest {
         },
         title: pr.title.unwrap_or_default(),
         message: pr.body.unwrap_or_default(),
+        open: pr.state == Some(IssueState::Open),
+        mergeable: pr.mergeable,
     }
 }
 
@@ -1,20 +1,23 @@
 //! Contains definitions of common types (pull request, user, repository name) needed
 //! for working with (GitHub) repositories.
 use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
 
+use serde::{Deserialize, Serialize};
 use url::Url;
 
 pub mod client;
+pub mod graphql;
 mod labels;
 pub mod misc;
 pub mod webhook;
 
-pub use client::MergeError;
+pub use client::{BranchUpdateError, MergeError, StatusError};
 pub use labels::{LabelModification, LabelTrigger};
 const API_ENDPOINT: &str = "https://api.github.com";
 
 /// Unique identifier of a GitHub repository
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
 pub struct GithubRepo {
     owner: String,
     name: String,
@@ -37,19 +40,33 @@ impl GithubRepo {
     }
 }
 
+/// Parses the full name of a repository (`owner/name`).
+impl FromStr for GithubRepo {
+    type Err = anyhow::Error;
+
+    fn from_str(full_name: &str) -> Result<Self, Self::Err> {
+        match full_name.split_once('/') {
+            Some((owner, name)) => Ok(Self::new(owner, name)),
+            None => Err(anyhow::anyhow!("Invalid repository name {full_name}")),
+        }
+    }
+}
+
 impl Display for GithubRepo {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_fmt(format_args!("{}/{}", self.owner, self.name))
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct GithubUser {
     pub username: String,
     pub html_url: Url,
+    /// Account is a bot (GitHub App or other automation).
+    pub is_bot: bool,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CommitSha(pub String);
 
 impl From<String> for CommitSha {
@@ -68,13 +85,13 @@ impl Display for CommitSha {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Branch {
     pub name: String,
     pub sha: CommitSha,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PullRequest {
     pub number: PullRequestNumber,
     /// <author>:<branch>
@@ -83,6 +100,9 @@ pub struct PullRequest {
     pub base: Branch,
     pub title: String,
     pub message: String,
+    pub open: bool,
+    /// `None` while GitHub computes it (e.g. after a push to the base branch).
+    pub mergeable: Option<bool>,
 }
 
 pub type PullRequestNumber = u64;
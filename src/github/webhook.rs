@@ -7,18 +7,20 @@ use sha2::Sha256;
 
 use super::misc::{WorkflowStatus, WorkflowType};
 use crate::bors::event::{
-    BorsEvent, CheckSuiteCompleted, PullRequestComment, WorkflowCompleted, WorkflowStarted, PR,
+    BorsEvent, BranchPushed, CheckSuiteCompleted, InstallationsChanged, MergeGroup,
+    MergeGroupDestroyed, MergeGroupDestroyedReason, PullRequestClosed, PullRequestComment,
+    PullRequestUpdated, WorkflowCompleted, WorkflowStarted, PR,
 };
 use crate::cf::Req;
 use crate::config::WEBHOOK_SECRET;
-use crate::github::{CommitSha, GithubRepo, GithubUser};
+use crate::github::{CommitSha, GithubRepo, GithubUser, PullRequestNumber};
 use crate::models::apps::App;
 use crate::models::events::payload::{
     IssueCommentEventAction, IssueCommentEventPayload, PullRequestReviewCommentEventAction,
     PullRequestReviewCommentEventPayload,
 };
 use crate::models::pulls::Review;
-use crate::models::{workflows, Author, CheckRun, Repository, RunId};
+use crate::models::{workflows, Author, CheckRun, Installation, Repository, RunId};
 
 /// This struct is used to extract the repository and user from a GitHub webhook event.
 /// The wrapper exists because octocrab doesn't expose/parse the repository field.
@@ -72,35 +74,200 @@ pub struct WebhookPullRequestReviewEvent<'a> {
     sender: Author,
 }
 
+/// Only the name of a repository; installation webhooks carry just a short summary
+/// of each repository and push webhooks use a different format of timestamps.
+#[derive(serde::Deserialize, Debug)]
+pub struct RepositorySummary {
+    full_name: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct WebhookInstallation<'a> {
+    action: &'a str,
+    installation: Installation,
+    #[serde(default)]
+    repositories: Option<Vec<RepositorySummary>>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct WebhookInstallationRepositories<'a> {
+    action: &'a str,
+    installation: Installation,
+    #[serde(default)]
+    repositories_added: Vec<RepositorySummary>,
+    #[serde(default)]
+    repositories_removed: Vec<RepositorySummary>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct MergeGroupInner {
+    head_sha: String,
+    head_ref: String,
+    base_sha: String,
+    base_ref: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct WebhookMergeGroup<'a> {
+    action: &'a str,
+    /// Only present for `destroyed` events
+    reason: Option<&'a str>,
+    merge_group: MergeGroupInner,
+    repository: Repository,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct PushCommit {
+    id: String,
+    #[serde(default)]
+    added: Vec<String>,
+    #[serde(default)]
+    modified: Vec<String>,
+}
+
+/// Push webhooks differ from [`crate::models::events::payload::PushEventPayload`]
+/// of the events API (e.g. commits have `id` instead of `sha`).
+#[derive(serde::Deserialize, Debug)]
+pub struct WebhookPush {
+    r#ref: String,
+    #[serde(default)]
+    deleted: bool,
+    commits: Vec<PushCommit>,
+    repository: RepositorySummary,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct PullRequestHead {
+    sha: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct PullRequestInner {
+    head: PullRequestHead,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct WebhookPullRequest<'a> {
+    action: &'a str,
+    number: PullRequestNumber,
+    pull_request: PullRequestInner,
+    repository: Repository,
+}
+
 /// extractor for GitHub webhook events.
-#[derive(Debug)]
-pub struct GitHubWebhook(pub BorsEvent);
+///
+/// It is serializable, so that it can be sent to the queue of webhooks.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct GitHubWebhook {
+    pub event: BorsEvent,
+    /// Unique ID of the delivery (`X-GitHub-Delivery`), kept when GitHub redelivers it.
+    pub delivery: Option<String>,
+}
+
+/// Why a webhook request was rejected.
+#[derive(Debug, thiserror::Error)]
+pub enum WebhookError {
+    #[error("Cannot read the request body: {0}")]
+    Body(String),
+    #[error("Missing header `{0}`")]
+    MissingHeader(&'static str),
+    #[error("Webhook secret is not configured")]
+    MissingSecret,
+    #[error("Invalid signature")]
+    InvalidSignature,
+    #[error("Invalid `{event}` payload: {error:?}")]
+    InvalidPayload { event: String, error: anyhow::Error },
+}
+
+impl WebhookError {
+    /// Status code of the response to GitHub.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            WebhookError::Body(_)
+            | WebhookError::MissingHeader(_)
+            | WebhookError::InvalidPayload { .. } => StatusCode::BAD_REQUEST,
+            WebhookError::InvalidSignature => StatusCode::UNAUTHORIZED,
+            WebhookError::MissingSecret => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn log(&self) {
+        let status = self.status().as_u16();
+        match self {
+            WebhookError::Body(error) => {
+                tracing::warn!(status, error, "Webhook request rejected, cannot read body")
+            }
+            WebhookError::MissingHeader(header) => {
+                tracing::warn!(status, header, "Webhook request rejected, missing header")
+            }
+            WebhookError::MissingSecret => {
+                tracing::error!(
+                    status,
+                    "Webhook request rejected, WEBHOOK_SECRET is not set"
+                )
+            }
+            WebhookError::InvalidSignature => {
+                tracing::error!(
+                    status,
+                    "Webhook request rejected, could not authenticate webhook"
+                )
+            }
+            WebhookError::InvalidPayload { event, error } => tracing::error!(
+                status,
+                event,
+                error = format!("{error:?}"),
+                "Webhook request rejected, cannot parse event"
+            ),
+        }
+    }
+}
 
 /// Extracts a webhook event from a HTTP request.
 impl GitHubWebhook {
-    pub async fn from_request(req: &mut worker::Request) -> Result<Self, StatusCode> {
-        let body = req.bytes().await.unwrap();
+    /// Returns `None` for events the bot does not handle.
+    pub async fn from_request(req: &mut worker::Request) -> Result<Option<Self>, WebhookError> {
+        let result = match req.bytes().await {
+            Ok(body) => Self::from_parts(
+                req.get_header("x-hub-signature-256").as_deref(),
+                req.get_header("x-github-event").as_deref(),
+                req.get_header("x-github-delivery"),
+                &body,
+                WEBHOOK_SECRET.get().map(String::as_str),
+            ),
+            Err(error) => Err(WebhookError::Body(error.to_string())),
+        };
+        if let Err(error) = &result {
+            error.log();
+        }
+        result
+    }
+
+    fn from_parts(
+        signature: Option<&str>,
+        event_type: Option<&str>,
+        delivery: Option<String>,
+        body: &[u8],
+        secret: Option<&str>,
+    ) -> Result<Option<Self>, WebhookError> {
         // Verify that the request is valid
-        if !verify_gh_signature(
-            &req.get_header("x-hub-signature-256").unwrap(),
-            &body,
-            WEBHOOK_SECRET.get().expect("Webhook expected!"),
-        ) {
-            tracing::error!("Webhook request failed, could not authenticate webhook");
-            return Err(StatusCode::BAD_REQUEST);
+        let signature = signature.ok_or(WebhookError::MissingHeader("x-hub-signature-256"))?;
+        let secret = secret.ok_or(WebhookError::MissingSecret)?;
+        if !verify_gh_signature(signature, body, secret) {
+            return Err(WebhookError::InvalidSignature);
         }
 
         // Parse webhook content
-        match parse_webhook_event(&req.get_header("x-github-event").unwrap(), &body) {
+        let event_type = event_type.ok_or(WebhookError::MissingHeader("x-github-event"))?;
+        match parse_webhook_event(event_type, body) {
             Ok(Some(event)) => {
                 tracing::trace!("Received webhook event {event:?}");
-                Ok(GitHubWebhook(event))
-            }
-            Ok(None) => Err(StatusCode::OK),
-            Err(error) => {
-                tracing::error!("Cannot parse webhook event: {error:?}");
-                Err(StatusCode::BAD_REQUEST)
+                Ok(Some(GitHubWebhook { event, delivery }))
             }
+            Ok(None) => Ok(None),
+            Err(error) => Err(WebhookError::InvalidPayload {
+                event: event_type.to_owned(),
+                error,
+            }),
         }
     }
 }
@@ -146,7 +313,106 @@ fn parse_webhook_event(event_type: &str, body: &[u8]) -> anyhow::Result<Option<B
                 Ok(None)
             }
         }
-        b"installation_repositories" | b"installation" => Ok(Some(BorsEvent::InstallationsChanged)),
+        b"installation" => {
+            let payload: WebhookInstallation = serde_json::from_slice(body)?;
+            let repositories =
+                parse_installation_repositories(payload.repositories.unwrap_or_default())?;
+            let (added, removed, uninstalled) = match payload.action {
+                "created" | "unsuspend" => (repositories, vec![], false),
+                "deleted" | "suspend" => (vec![], repositories, true),
+                _ => return Ok(None),
+            };
+            Ok(Some(BorsEvent::InstallationsChanged(
+                InstallationsChanged {
+                    installation: payload.installation,
+                    added,
+                    removed,
+                    uninstalled,
+                },
+            )))
+        }
+        b"installation_repositories" => {
+            let payload: WebhookInstallationRepositories = serde_json::from_slice(body)?;
+            match payload.action {
+                "added" | "removed" => Ok(Some(BorsEvent::InstallationsChanged(
+                    InstallationsChanged {
+                        installation: payload.installation,
+                        added: parse_installation_repositories(payload.repositories_added)?,
+                        removed: parse_installation_repositories(payload.repositories_removed)?,
+                        uninstalled: false,
+                    },
+                ))),
+                _ => Ok(None),
+            }
+        }
+        b"merge_group" => {
+            let payload: WebhookMergeGroup = serde_json::from_slice(body)?;
+            let group = parse_merge_group(&payload.repository, payload.merge_group)?;
+            match (payload.action, payload.reason) {
+                ("checks_requested", _) => Ok(Some(BorsEvent::MergeGroupChecksRequested(group))),
+                ("destroyed", Some(reason)) => {
+                    let reason = match reason {
+                        "merged" => MergeGroupDestroyedReason::Merged,
+                        "invalidated" => MergeGroupDestroyedReason::Invalidated,
+                        "dequeued" => MergeGroupDestroyedReason::Dequeued,
+                        _ => {
+                            tracing::warn!("Ignoring merge group destroyed with reason {reason}");
+                            return Ok(None);
+                        }
+                    };
+                    Ok(Some(BorsEvent::MergeGroupDestroyed(MergeGroupDestroyed {
+                        group,
+                        reason,
+                    })))
+                }
+                _ => Ok(None),
+            }
+        }
+        b"push" => {
+            let payload: WebhookPush = serde_json::from_slice(body)?;
+            // Tags and deleted branches are not interesting
+            let Some(branch) = payload.r#ref.strip_prefix("refs/heads/") else {
+                return Ok(None);
+            };
+            if payload.deleted || payload.commits.is_empty() {
+                return Ok(None);
+            }
+            let mut changed_files: Vec<String> = payload
+                .commits
+                .iter()
+                .flat_map(|commit| commit.added.iter().chain(commit.modified.iter()))
+                .cloned()
+                .collect();
+            changed_files.sort();
+            changed_files.dedup();
+            Ok(Some(BorsEvent::BranchPushed(BranchPushed {
+                repository: payload.repository.full_name.parse()?,
+                branch: branch.to_owned(),
+                commits: payload
+                    .commits
+                    .into_iter()
+                    .map(|commit| CommitSha(commit.id))
+                    .collect(),
+                changed_files,
+            })))
+        }
+        b"pull_request" => {
+            let payload: WebhookPullRequest = serde_json::from_slice(body)?;
+            match payload.action {
+                "opened" | "reopened" | "synchronize" => {
+                    Ok(Some(BorsEvent::PullRequestUpdated(PullRequestUpdated {
+                        repository: parse_repository_name(&payload.repository)?,
+                        number: payload.number,
+                        head_sha: CommitSha(payload.pull_request.head.sha),
+                    })))
+                }
+                "closed" => Ok(Some(BorsEvent::PullRequestClosed(PullRequestClosed {
+                    repository: parse_repository_name(&payload.repository)?,
+                    number: payload.number,
+                }))),
+                _ => Ok(None),
+            }
+        }
         b"workflow_run" => {
             let payload: WebhookWorkflowRun = serde_json::from_slice(body)?;
             let repository_name = parse_repository_name(&payload.repository)?;
@@ -175,7 +441,7 @@ fn parse_webhook_event(event_type: &str, body: &[u8]) -> anyhow::Result<Option<B
             Ok(result)
         }
         b"check_run" => {
-            let payload: WebhookCheckRun = serde_json::from_slice(body).unwrap();
+            let payload: WebhookCheckRun = serde_json::from_slice(body)?;
 
             // We are only interested in check runs from external CI services.
             // These basically correspond to workflow runs from GHA.
@@ -249,6 +515,7 @@ fn parse_comment_from_pr_review(
 
 fn parse_user(user: Author) -> GithubUser {
     GithubUser {
+        is_bot: user.r#type == "Bot",
         username: user.login,
         html_url: user.html_url,
     }
@@ -277,6 +544,44 @@ fn parse_pr_comment(
     })
 }
 
+fn parse_installation_repositories(
+    repositories: Vec<RepositorySummary>,
+) -> anyhow::Result<Vec<GithubRepo>> {
+    repositories
+        .into_iter()
+        .map(|repository| repository.full_name.parse())
+        .collect()
+}
+
+fn parse_merge_group(
+    repository: &Repository,
+    group: MergeGroupInner,
+) -> anyhow::Result<MergeGroup> {
+    let head_ref = strip_heads(group.head_ref);
+    Ok(MergeGroup {
+        repository: parse_repository_name(repository)?,
+        pull_request: parse_merge_group_pr(&head_ref),
+        head_ref,
+        head_sha: CommitSha(group.head_sha),
+        base_ref: strip_heads(group.base_ref),
+        base_sha: CommitSha(group.base_sha),
+    })
+}
+
+fn strip_heads(reference: String) -> String {
+    match reference.strip_prefix("refs/heads/") {
+        Some(branch) => branch.to_owned(),
+        None => reference,
+    }
+}
+
+/// Extracts the PR number from `gh-readonly-queue/{base}/pr-{number}-{sha}` branches.
+fn parse_merge_group_pr(head_ref: &str) -> Option<PullRequestNumber> {
+    let (_, last) = head_ref.rsplit_once("/pr-")?;
+    let (number, _) = last.split_once('-')?;
+    number.parse().ok()
+}
+
 fn parse_repository_name(repository: &Repository) -> anyhow::Result<GithubRepo> {
     let repo_name = &repository.name;
     let Some(repo_owner) = repository
@@ -300,3 +605,256 @@ fn verify_gh_signature(signature: &str, body: &[u8], secret: &str) -> bool {
     mac.update(body);
     mac.verify_slice(&signature).is_ok()
 }
+
+#[cfg(test)]
+mod tests {
+    use hmac::Mac;
+    use http::StatusCode;
+
+    use crate::bors::event::{BorsEvent, MergeGroupDestroyedReason};
+    use crate::github::webhook::{parse_webhook_event, GitHubWebhook, HmacSha256, WebhookError};
+
+    const SECRET: &str = "secret";
+    const PUSH: &[u8] = include_bytes!("../../tests/data/webhook/push.json");
+
+    fn sign(body: &[u8]) -> String {
+        let mut mac = HmacSha256::new_from_slice(SECRET.as_bytes()).unwrap();
+        mac.update(body);
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    fn from_parts(
+        signature: Option<&str>,
+        event_type: Option<&str>,
+        body: &[u8],
+    ) -> Result<Option<GitHubWebhook>, WebhookError> {
+        GitHubWebhook::from_parts(
+            signature,
+            event_type,
+            Some("72d3162e-cc78-11e3-81ab-4c9367dc0958".to_string()),
+            body,
+            Some(SECRET),
+        )
+    }
+
+    #[test]
+    fn request_valid() {
+        let webhook = from_parts(Some(&sign(PUSH)), Some("push"), PUSH)
+            .unwrap()
+            .unwrap();
+        assert!(matches!(webhook.event, BorsEvent::BranchPushed(_)));
+        assert_eq!(
+            webhook.delivery.as_deref(),
+            Some("72d3162e-cc78-11e3-81ab-4c9367dc0958")
+        );
+    }
+
+    #[test]
+    fn request_serialize() {
+        let webhook = from_parts(Some(&sign(PUSH)), Some("push"), PUSH)
+            .unwrap()
+            .unwrap();
+        let json = serde_json::to_string(&webhook).unwrap();
+        let webhook: GitHubWebhook = serde_json::from_str(&json).unwrap();
+        let BorsEvent::BranchPushed(payload) = webhook.event else {
+            panic!("unexpected event {:?}", webhook.event);
+        };
+        assert_eq!(payload.repository.to_string(), "kobzol/bors-kindergarten");
+        assert_eq!(payload.branch, "main");
+        assert_eq!(payload.changed_files, vec!["test.txt"]);
+        assert_eq!(
+            webhook.delivery.as_deref(),
+            Some("72d3162e-cc78-11e3-81ab-4c9367dc0958")
+        );
+    }
+
+    #[test]
+    fn request_unhandled_event() {
+        let body = br#"{"zen": "Keep it logically awesome."}"#;
+        assert!(from_parts(Some(&sign(body)), Some("ping"), body)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn request_missing_signature() {
+        let error = from_parts(None, Some("push"), PUSH).err().unwrap();
+        assert!(matches!(
+            error,
+            WebhookError::MissingHeader("x-hub-signature-256")
+        ));
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn request_missing_secret() {
+        let error = GitHubWebhook::from_parts(Some(&sign(PUSH)), Some("push"), None, PUSH, None)
+            .err()
+            .unwrap();
+        assert!(matches!(error, WebhookError::MissingSecret));
+        assert_eq!(error.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn request_invalid_signature() {
+        for signature in ["sha256=00", "md5=abc", &sign(b"other body")] {
+            let error = from_parts(Some(signature), Some("push"), PUSH)
+                .err()
+                .unwrap();
+            assert!(matches!(error, WebhookError::InvalidSignature));
+            assert_eq!(error.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[test]
+    fn request_missing_event() {
+        let error = from_parts(Some(&sign(PUSH)), None, PUSH).err().unwrap();
+        assert!(matches!(
+            error,
+            WebhookError::MissingHeader("x-github-event")
+        ));
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn request_invalid_payload() {
+        for (event, body) in [
+            ("push", &b"not json"[..]),
+            ("push", br#"{"ref": "refs/heads/main"}"#),
+            ("issue_comment", b"{}"),
+            ("check_run", br#"{"action": "created"}"#),
+        ] {
+            let error = from_parts(Some(&sign(body)), Some(event), body)
+                .err()
+                .unwrap();
+            let WebhookError::InvalidPayload { event: name, .. } = &error else {
+                panic!("unexpected error {error:?}");
+            };
+            assert_eq!(name, event);
+            assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[test]
+    fn push() {
+        let event =
+            parse_webhook_event("push", include_bytes!("../../tests/data/webhook/push.json"))
+                .unwrap();
+        let Some(BorsEvent::BranchPushed(payload)) = event else {
+            panic!("unexpected event {event:?}");
+        };
+        assert_eq!(payload.branch, "main");
+        assert_eq!(payload.commits.len(), 1);
+        assert_eq!(
+            payload.commits[0].0,
+            "bc7370e473896a94d40a7dff71f197a3ff0208f5"
+        );
+        assert_eq!(payload.changed_files, vec!["test.txt"]);
+    }
+
+    #[test]
+    fn pull_request_synchronize() {
+        let event = parse_webhook_event(
+            "pull_request",
+            include_bytes!("../../tests/data/webhook/pull-request-synchronize.json"),
+        )
+        .unwrap();
+        let Some(BorsEvent::PullRequestUpdated(payload)) = event else {
+            panic!("unexpected event {event:?}");
+        };
+        assert_eq!(payload.repository.to_string(), "kobzol/bors-kindergarten");
+        assert_eq!(payload.number, 6);
+        assert_eq!(
+            payload.head_sha.0,
+            "dd929458c62ac2a2958a5dadde1465732dff6f90"
+        );
+    }
+
+    #[test]
+    fn pull_request_closed() {
+        let event = parse_webhook_event(
+            "pull_request",
+            include_bytes!("../../tests/data/webhook/pull-request-closed.json"),
+        )
+        .unwrap();
+        let Some(BorsEvent::PullRequestClosed(payload)) = event else {
+            panic!("unexpected event {event:?}");
+        };
+        assert_eq!(payload.repository.to_string(), "kobzol/bors-kindergarten");
+        assert_eq!(payload.number, 6);
+    }
+
+    #[test]
+    fn merge_group_checks_requested() {
+        let event = parse_webhook_event(
+            "merge_group",
+            include_bytes!("../../tests/data/webhook/merge-group-checks-requested.json"),
+        )
+        .unwrap();
+        let Some(BorsEvent::MergeGroupChecksRequested(group)) = event else {
+            panic!("unexpected event {event:?}");
+        };
+        assert_eq!(
+            group.head_ref,
+            "gh-readonly-queue/main/pr-12-c0a2d1ef6e7c0b2e4f0b0d52e1a0f1a1c7e3b8a4"
+        );
+        assert_eq!(group.base_ref, "main");
+        assert_eq!(
+            group.base_sha.as_ref(),
+            "c0a2d1ef6e7c0b2e4f0b0d52e1a0f1a1c7e3b8a4"
+        );
+        assert_eq!(group.pull_request, Some(12));
+    }
+
+    #[test]
+    fn merge_group_destroyed() {
+        let event = parse_webhook_event(
+            "merge_group",
+            include_bytes!("../../tests/data/webhook/merge-group-destroyed.json"),
+        )
+        .unwrap();
+        let Some(BorsEvent::MergeGroupDestroyed(payload)) = event else {
+            panic!("unexpected event {event:?}");
+        };
+        assert_eq!(payload.reason, MergeGroupDestroyedReason::Dequeued);
+        assert_eq!(payload.group.pull_request, Some(12));
+    }
+
+    #[test]
+    fn merge_group_destroyed_unknown_reason() {
+        let body = String::from_utf8_lossy(include_bytes!(
+            "../../tests/data/webhook/merge-group-destroyed.json"
+        ))
+        .replace(r#""reason": "dequeued""#, r#""reason": "exploded""#);
+        let event = parse_webhook_event("merge_group", body.as_bytes()).unwrap();
+        assert!(event.is_none());
+    }
+
+    #[test]
+    fn installation_suspend() {
+        let event = parse_webhook_event(
+            "installation",
+            include_bytes!("../../tests/data/webhook/installation-suspend.json"),
+        )
+        .unwrap();
+        let Some(BorsEvent::InstallationsChanged(payload)) = event else {
+            panic!("unexpected event {event:?}");
+        };
+        assert_eq!(payload.installation.id.0, 32739733);
+        assert!(payload.uninstalled);
+        assert!(payload.added.is_empty());
+    }
+
+    #[test]
+    fn installation_unsuspend() {
+        let event = parse_webhook_event(
+            "installation",
+            include_bytes!("../../tests/data/webhook/installation-unsuspend.json"),
+        )
+        .unwrap();
+        let Some(BorsEvent::InstallationsChanged(payload)) = event else {
+            panic!("unexpected event {event:?}");
+        };
+        assert!(!payload.uninstalled);
+    }
+}
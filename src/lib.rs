@@ -1,28 +1,44 @@
 //! This is the library of the bors bot.
 pub mod bors;
+pub mod cache;
 pub mod config;
 pub mod github;
+pub mod messages;
 pub mod models;
 pub mod permissions;
+pub mod store;
 pub mod utils;
 
+use bors::event::BorsEvent;
 use bors::handle_bors_event;
-use config::{APP_ID, CMD_PREFIX, PAT, PRIVATE_KEY, WEBHOOK_SECRET};
+use config::{APP_ID, CMD_PREFIX, ORG_CONFIG_REPO, PAT, PRIVATE_KEY, WEBHOOK_SECRET};
 pub use console_error_panic_hook::set_once as set_panic_hook;
+use github::client::AutoClientError;
 use github::webhook::GitHubWebhook;
+use store::{
+    deferred_webhook_key, delivery_key, DeferredWebhook, DeliveryState, ProcessedDelivery, Store,
+    DEFERRED_WEBHOOK_PREFIX, DEFERRED_WEBHOOK_TTL, DELIVERY_IN_PROGRESS_TTL, DELIVERY_TTL,
+};
+use tracing::{Instrument, Span};
 use tracing_subscriber::fmt::format::Pretty;
 use tracing_subscriber::fmt::time::UtcTime;
 use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_web::{performance_layer, MakeConsoleWriter};
+use utils::time::now;
 use worker::*;
 
 mod cf;
 
+/// Binding of the queue of webhooks (see `wrangler.toml`)
+const QUEUE_BINDING: &str = "WEBHOOKS";
+
 //.route("/github", post(github_webhook_handler))
 
-#[event(fetch, respond_with_errors)]
-pub async fn main(req: Request, env: Env, _ctx: worker::Context) -> Result<Response> {
+/// Sets up logging and reads the configuration from the environment.
+///
+/// The isolate may be reused for several events, so this must be idempotent.
+fn init(env: &Env) {
     set_panic_hook();
     // tracer
     let fmt_layer = tracing_subscriber::fmt::layer()
@@ -33,29 +49,25 @@ pub async fn main(req: Request, env: Env, _ctx: worker::Context) -> Result<Respo
         .with_level(true)
         .with_target(false);
     let perf_layer = performance_layer().with_details_from_fields(Pretty::default());
-    tracing_subscriber::registry()
+    let _ = tracing_subscriber::registry()
         .with(fmt_layer)
         .with(perf_layer)
-        .init();
+        .try_init();
 
     // make sure of env
     if let Ok(pat) = env.secret("PAT") {
-        PAT.set(pat.to_string()).unwrap();
+        let _ = PAT.set(pat.to_string());
     }
-    WEBHOOK_SECRET
-        .set(
-            env.secret("WEBHOOK_SECRET")
-                .expect("No WEBHOOK_SECRET secret")
-                .to_string(),
-        )
-        .unwrap();
-    CMD_PREFIX
-        .set(
-            env.var("CMD_PREFIX")
-                .map(|x| x.to_string())
-                .unwrap_or("@bors-servo".to_string()),
-        )
-        .unwrap();
+    let _ = WEBHOOK_SECRET.set(
+        env.secret("WEBHOOK_SECRET")
+            .expect("No WEBHOOK_SECRET secret")
+            .to_string(),
+    );
+    let _ = CMD_PREFIX.set(
+        env.var("CMD_PREFIX")
+            .map(|x| x.to_string())
+            .unwrap_or("@bors-servo".to_string()),
+    );
     /*
     there are actually two modes we could run
     - pat only, where we need to manually register hook(s)
@@ -64,29 +76,268 @@ pub async fn main(req: Request, env: Env, _ctx: worker::Context) -> Result<Respo
     */
     // so these are optional
     if let Ok(app_id) = env.secret("APP_ID") {
-        APP_ID.set(app_id.to_string()).unwrap();
+        let _ = APP_ID.set(app_id.to_string());
     }
     if let Ok(private_key) = env.secret("PRIVATE_KEY") {
-        PRIVATE_KEY.set(private_key.to_string()).unwrap();
+        let _ = PRIVATE_KEY.set(private_key.to_string());
+    }
+    if let Ok(source) = env.var("ORG_CONFIG_REPO") {
+        match source.to_string().parse() {
+            Ok(source) => {
+                let _ = ORG_CONFIG_REPO.set(source);
+            }
+            Err(error) => tracing::error!("Invalid ORG_CONFIG_REPO: {error:?}"),
+        }
+    }
+}
+
+/// Cron trigger (see `wrangler.toml`), checks e.g. timeouts of builds.
+#[event(scheduled)]
+pub async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    init(&env);
+    let store = Store::new(&env);
+    if let Err(error) = handle_bors_event(BorsEvent::Refresh, &store).await {
+        tracing::error!("Refresh failed: {error:?}");
+    }
+    if let Err(error) = requeue_deferred_webhooks(&env, &store).await {
+        tracing::error!("Cannot queue deferred webhooks: {error:?}");
+    }
+}
+
+/// Sends the webhooks deferred by a rate limit back to the queue once the limit reset.
+async fn requeue_deferred_webhooks(env: &Env, store: &Store) -> anyhow::Result<()> {
+    let keys = store.keys(DEFERRED_WEBHOOK_PREFIX).await?;
+    if keys.is_empty() {
+        return Ok(());
+    }
+    let queue = env
+        .queue(QUEUE_BINDING)
+        .map_err(|error| anyhow::anyhow!("Cannot get the queue: {error:?}"))?;
+    for key in keys {
+        let Some(deferred) = store.get::<DeferredWebhook>(&key).await? else {
+            continue;
+        };
+        if deferred.retry_at > now() {
+            continue;
+        }
+        tracing::info!(
+            delivery = deferred.webhook.delivery,
+            "Queueing webhook deferred by a rate limit"
+        );
+        queue
+            .send(&deferred.webhook)
+            .await
+            .map_err(|error| anyhow::anyhow!("Cannot queue {key}: {error:?}"))?;
+        store.delete(&key).await?;
     }
+    Ok(())
+}
+
+/// Span of the handling of a webhook, see [`handle_webhook`].
+fn webhook_span(webhook: &GitHubWebhook) -> Span {
+    tracing::info_span!(
+        "Webhook",
+        delivery = webhook.delivery.as_deref(),
+        duplicate = tracing::field::Empty
+    )
+}
+
+/// Handles the webhook event, unless its delivery was already processed.
+///
+/// GitHub redelivers webhooks (e.g. manually from the app settings), which would run
+/// the commands again. Errors are logged here, the result only tells whether the webhook
+/// should be retried.
+async fn handle_webhook(webhook: GitHubWebhook, store: Store) -> anyhow::Result<()> {
+    let key = webhook.delivery.as_deref().map(delivery_key);
+    if let Some(key) = &key {
+        match store.get::<ProcessedDelivery>(key).await {
+            Ok(processed) => match check_delivery(processed.as_ref()) {
+                DeliveryCheck::Handle => {}
+                DeliveryCheck::Duplicate => {
+                    Span::current().record("duplicate", true);
+                    if let Some(processed) = processed {
+                        tracing::info!(
+                            "Ignoring webhook delivery processed at {}",
+                            processed.processed_at
+                        );
+                    }
+                    return Ok(());
+                }
+                // Either handled concurrently or the worker crashed, the marker expires
+                // before the queue retries, so a retry finds out which one it was
+                DeliveryCheck::Busy => {
+                    tracing::info!("Webhook delivery is being handled, retrying later");
+                    return Err(anyhow::anyhow!("Webhook delivery is being handled"));
+                }
+            },
+            Err(error) => tracing::warn!("Cannot check webhook delivery: {error:?}"),
+        }
+        Span::current().record("duplicate", false);
+        remember_delivery(
+            &store,
+            key,
+            DeliveryState::InProgress,
+            DELIVERY_IN_PROGRESS_TTL,
+        )
+        .await;
+    }
+    if let Err(error) = handle_bors_event(webhook.event, &store).await {
+        tracing::error!(
+            delivery = webhook.delivery,
+            "Webhook handling failed: {error:?}"
+        );
+        // Only temporary errors end up here and the handlers can repeat what they already
+        // did, so the retry of the queue (or a manual redelivery) may handle it again
+        if let Some(key) = &key {
+            remember_delivery(&store, key, DeliveryState::Retry, DELIVERY_TTL).await;
+        }
+        return Err(error);
+    }
+    if let Some(key) = &key {
+        remember_delivery(&store, key, DeliveryState::Done, DELIVERY_TTL).await;
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum DeliveryCheck {
+    Handle,
+    Duplicate,
+    Busy,
+}
+
+/// Deliveries are handled once, unless their handling failed temporarily.
+fn check_delivery(processed: Option<&ProcessedDelivery>) -> DeliveryCheck {
+    match processed.map(|processed| processed.state) {
+        None | Some(DeliveryState::Retry) => DeliveryCheck::Handle,
+        Some(DeliveryState::Done) => DeliveryCheck::Duplicate,
+        Some(DeliveryState::InProgress) => DeliveryCheck::Busy,
+    }
+}
+
+async fn remember_delivery(store: &Store, key: &str, state: DeliveryState, ttl: u64) {
+    let processed = ProcessedDelivery {
+        processed_at: now(),
+        state,
+    };
+    if let Err(error) = store.put_with_ttl(key, &processed, ttl).await {
+        tracing::warn!("Cannot remember webhook delivery: {error:?}");
+    }
+}
+
+/// Queue consumer (see `wrangler.toml`), handles the webhooks queued by the `/app` route.
+///
+/// Failed webhooks are retried by the queue, and moved to its dead-letter queue after
+/// the last retry. Webhooks that hit the API rate limit are deferred until it resets instead,
+/// the retries of the queue would be used up long before.
+#[event(queue)]
+pub async fn queue(
+    batch: MessageBatch<GitHubWebhook>,
+    env: Env,
+    _ctx: worker::Context,
+) -> Result<()> {
+    init(&env);
+    let store = Store::new(&env);
+    for message in batch.messages()? {
+        let span = webhook_span(&message.body);
+        let Err(error) = handle_webhook(message.body.clone(), store.clone())
+            .instrument(span)
+            .await
+        else {
+            continue;
+        };
+        let deferred = match (rate_limit_reset(&error), &message.body.delivery) {
+            (Some(wait), Some(delivery)) => {
+                defer_webhook(&store, delivery, &message.body, wait).await
+            }
+            _ => false,
+        };
+        if !deferred {
+            message.retry();
+        }
+    }
+    Ok(())
+}
+
+/// Seconds until the API rate limit that failed the handling resets, if it was one.
+fn rate_limit_reset(error: &anyhow::Error) -> Option<u64> {
+    error.chain().find_map(|cause| match cause.downcast_ref() {
+        Some(AutoClientError::RateLimited(wait)) => Some(*wait),
+        _ => None,
+    })
+}
+
+/// Stores the webhook for [`requeue_deferred_webhooks`], returns whether it was stored.
+async fn defer_webhook(store: &Store, delivery: &str, webhook: &GitHubWebhook, wait: u64) -> bool {
+    let deferred = DeferredWebhook {
+        webhook: webhook.clone(),
+        retry_at: now() + chrono::Duration::seconds(wait as i64),
+    };
+    tracing::warn!(
+        delivery,
+        "API rate limit exhausted, deferring webhook by {wait}s"
+    );
+    match store
+        .put_with_ttl(
+            &deferred_webhook_key(delivery),
+            &deferred,
+            DEFERRED_WEBHOOK_TTL,
+        )
+        .await
+    {
+        Ok(()) => true,
+        Err(error) => {
+            tracing::warn!("Cannot defer webhook: {error:?}");
+            false
+        }
+    }
+}
+
+#[event(fetch, respond_with_errors)]
+pub async fn main(req: Request, env: Env, ctx: worker::Context) -> Result<Response> {
+    init(&env);
 
     // Create an instance of the Router, which can use parameters (/user/:name) or wildcard values
-    // (/file/*pathname). Alternatively, use `Router::with_data(D)` and pass in arbitrary data for
-    // routes to access and share using the `ctx.data()` method.
-    let router = Router::new();
+    // (/file/*pathname). The worker context is shared with routes, so that they can finish
+    // their work after responding (`ctx.data().wait_until`).
+    let router = Router::with_data(ctx);
 
     router
         // listener on app webhooks
-        .post_async("/app", |mut req, _ctx| async move {
+        .post_async("/app", |mut req, ctx| async move {
             match GitHubWebhook::from_request(&mut req).await {
-                Ok(webhook) => {
-                    if let Err(e) = handle_bors_event(webhook.0).await {
-                        Response::error(e.to_string(), 500)
-                    } else {
-                        Response::empty()
+                Ok(None) => Response::empty(),
+                Ok(Some(webhook)) => {
+                    // GitHub gives up on webhooks after 10 seconds,
+                    // so the event is handled after the response is sent
+                    match ctx.env.queue(QUEUE_BINDING) {
+                        Ok(queue) => {
+                            if let Err(error) = queue.send(&webhook).await {
+                                tracing::error!(
+                                    delivery = webhook.delivery,
+                                    "Cannot queue webhook: {error:?}"
+                                );
+                                return Response::error("Cannot queue webhook", 500);
+                            }
+                        }
+                        // Without the queue, there are no retries. Workers also cut off
+                        // `wait_until` work 30 seconds after the response, so slow handlers
+                        // (e.g. waiting for GitHub to compute mergeability) may not finish.
+                        Err(_) => {
+                            let store = Store::new(&ctx.env);
+                            let span = webhook_span(&webhook);
+                            ctx.data().wait_until(
+                                async move {
+                                    // Already logged
+                                    let _ = handle_webhook(webhook, store).await;
+                                }
+                                .instrument(span),
+                            );
+                        }
                     }
+                    Ok(Response::empty()?.with_status(202))
                 }
-                Err(e) => Response::error("Hooking error", e.as_u16()),
+                Err(error) => Response::error(error.to_string(), error.status().as_u16()),
             }
         })
         // listener on manual webhooks
@@ -117,3 +368,56 @@ pub async fn main(req: Request, env: Env, _ctx: worker::Context) -> Result<Respo
         .run(req, env)
         .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{check_delivery, rate_limit_reset, DeliveryCheck};
+    use crate::github::client::AutoClientError;
+    use crate::store::{DeliveryState, ProcessedDelivery};
+    use crate::utils::time::now;
+
+    fn processed(state: DeliveryState) -> ProcessedDelivery {
+        ProcessedDelivery {
+            processed_at: now(),
+            state,
+        }
+    }
+
+    #[test]
+    fn fresh_delivery() {
+        assert_eq!(check_delivery(None), DeliveryCheck::Handle);
+    }
+
+    #[test]
+    fn duplicate_delivery() {
+        assert_eq!(
+            check_delivery(Some(&processed(DeliveryState::Done))),
+            DeliveryCheck::Duplicate
+        );
+    }
+
+    #[test]
+    fn failed_delivery_retried() {
+        assert_eq!(
+            check_delivery(Some(&processed(DeliveryState::Retry))),
+            DeliveryCheck::Handle
+        );
+    }
+
+    #[test]
+    fn delivery_in_progress_retried_later() {
+        assert_eq!(
+            check_delivery(Some(&processed(DeliveryState::InProgress))),
+            DeliveryCheck::Busy
+        );
+    }
+
+    #[test]
+    fn rate_limited_handling() {
+        let error = anyhow::Error::new(AutoClientError::RateLimited(1200))
+            .context("Cannot get PR")
+            .context("Webhook handling failed");
+        assert_eq!(rate_limit_reset(&error), Some(1200));
+        assert_eq!(rate_limit_reset(&anyhow::anyhow!("Not found")), None);
+    }
+}
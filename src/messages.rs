@@ -0,0 +1,372 @@
+//! Messages posted by the bot, which can be overridden in the `[messages]` table
+//! of the config. Templates can use placeholders like `{pr}` or `{user}`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::github::{GithubRepo, PullRequestNumber};
+use crate::permissions::DenialReason;
+
+const MERGE_CONFLICT: &str = r#":lock: Merge conflict
+
+This pull request and the master branch diverged in a way that cannot
+ be automatically merged. Please rebase on top of the latest master
+ branch, and let the reviewer approve again.
+
+<details><summary>How do I rebase?</summary>
+
+Assuming `self` is your fork and `upstream` is this repository,
+ you can resolve the conflict following these steps:
+
+1. `git checkout {branch}` *(switch to your branch)*
+2. `git fetch upstream master` *(retrieve the latest master)*
+3. `git rebase upstream/master -p` *(rebase on top of it)*
+4. Follow the on-screen instruction to resolve conflicts (check `git status` if you got lost).
+5. `git push self {branch} --force-with-lease` *(update this PR)*
+
+You may also read
+ [*Git Rebasing to Resolve Conflicts* by Drew Blessing](http://blessing.io/git/git-rebase/open-source/2015/08/23/git-rebasing-to-resolve-conflicts.html)
+ for a short tutorial.
+
+Please avoid the ["**Resolve conflicts**" button](https://help.github.com/articles/resolving-a-merge-conflict-on-github/) on GitHub.
+ It uses `git merge` instead of `git rebase` which makes the PR commit history more difficult to read.
+
+Sometimes step 4 will complete without asking for resolution. This is usually due to difference between how `Cargo.lock` conflict is
+handled during merge and rebase. This is normal, and you should still perform step 5 to update this PR.
+
+</details>  
+"#;
+
+/// Keys of [`Messages`], others are reported by [`Config::validate`](crate::config::Config::validate)
+pub const MESSAGE_KEYS: &[&str] = &[
+    "ping",
+    "try_started",
+    "try_in_progress",
+    "try_timed_out",
+    "branch_update_failed",
+    "merge_conflict",
+    "permission_denied",
+    "merge_queue_left",
+    "merge_group_discarded",
+    "merge_queue_merged",
+];
+
+/// Templates of the messages, `None` uses the default message.
+///
+/// Inheritance: Override (per message)
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Messages {
+    /// Reply to `ping`, placeholders: `{user}`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ping: Option<String>,
+    /// Try build has started, placeholders: `{pr}`, `{sha}` (head of the PR), `{merge_sha}`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub try_started: Option<String>,
+    /// Try build of the same commit is already running, placeholders: `{pr}`, `{sha}`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub try_in_progress: Option<String>,
+    /// Try build was cancelled after the timeout, placeholders: `{pr}`, `{timeout}`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub try_timed_out: Option<String>,
+    /// Try branches cannot be updated, placeholders: `{pr}`, `{reason}`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch_update_failed: Option<String>,
+    /// The PR cannot be merged, placeholders: `{pr}`, `{branch}` (of the PR), `{base}`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub merge_conflict: Option<String>,
+    /// User cannot use the command, placeholders: `{user}`, `{reason}`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub permission_denied: Option<String>,
+    /// The PR left the merge queue without being merged,
+    /// placeholders: `{pr}`, `{reason}`, `{checks}` (failed checks of the merge group)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub merge_queue_left: Option<String>,
+    /// Merge group of a PR behind this one was discarded,
+    /// placeholders: `{pr}` (the PR behind), `{reason}`, `{checks}`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub merge_group_discarded: Option<String>,
+    /// The PR was merged by the merge queue, placeholders: `{pr}`, `{sha}`, `{branch}`,
+    /// `{workflows}` (with their durations), `{approvers}`, `{time_in_queue}`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub merge_queue_merged: Option<String>,
+}
+
+/// Replaces `{name}` placeholders with their values, unknown placeholders are kept.
+///
+/// Values are inserted as they are, placeholders in them (e.g. in a PR title) are not expanded.
+pub fn render(template: &str, values: &[(&str, &str)]) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let placeholder = after.find('}').and_then(|end| {
+            let name = &after[..end];
+            values
+                .iter()
+                .find(|(known, _)| *known == name)
+                .map(|(_, value)| (*value, end))
+        });
+        match placeholder {
+            Some((value, end)) => {
+                rendered.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = after;
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+impl Messages {
+    /// Overrides messages of `self` (org) with the ones set in `local`.
+    pub fn merge(&mut self, local: Messages) {
+        self.ping = local.ping.or(self.ping.take());
+        self.try_started = local.try_started.or(self.try_started.take());
+        self.try_in_progress = local.try_in_progress.or(self.try_in_progress.take());
+        self.try_timed_out = local.try_timed_out.or(self.try_timed_out.take());
+        self.branch_update_failed = local
+            .branch_update_failed
+            .or(self.branch_update_failed.take());
+        self.merge_conflict = local.merge_conflict.or(self.merge_conflict.take());
+        self.permission_denied = local.permission_denied.or(self.permission_denied.take());
+        self.merge_queue_left = local.merge_queue_left.or(self.merge_queue_left.take());
+        self.merge_group_discarded = local
+            .merge_group_discarded
+            .or(self.merge_group_discarded.take());
+        self.merge_queue_merged = local.merge_queue_merged.or(self.merge_queue_merged.take());
+    }
+
+    pub fn ping(&self, repo: &GithubRepo, user: &str) -> String {
+        match &self.ping {
+            Some(template) => render(template, &[("user", user)]),
+            None if repo.owner() == "servo" => ":sleepy: I'm awake I'm awake".to_string(),
+            None => "Pong 🏓!".to_string(),
+        }
+    }
+
+    pub fn try_started(&self, pr: PullRequestNumber, sha: &str, merge_sha: &str) -> String {
+        let template = self
+            .try_started
+            .as_deref()
+            .unwrap_or(":hourglass: Trying commit {sha} with merge {merge_sha}…");
+        render(
+            template,
+            &[
+                ("pr", &pr.to_string()),
+                ("sha", sha),
+                ("merge_sha", merge_sha),
+            ],
+        )
+    }
+
+    pub fn try_in_progress(&self, pr: PullRequestNumber, sha: &str) -> String {
+        let template = self
+            .try_in_progress
+            .as_deref()
+            .unwrap_or(":exclamation: A try build of {sha} is already in progress.");
+        render(template, &[("pr", &pr.to_string()), ("sha", sha)])
+    }
+
+    pub fn try_timed_out(&self, pr: PullRequestNumber, timeout: &str) -> String {
+        let template = self
+            .try_timed_out
+            .as_deref()
+            .unwrap_or(":boom: Test timed out after {timeout}");
+        render(template, &[("pr", &pr.to_string()), ("timeout", timeout)])
+    }
+
+    pub fn branch_update_failed(&self, pr: PullRequestNumber, reason: &str) -> String {
+        let template = self
+            .branch_update_failed
+            .as_deref()
+            .unwrap_or(":x: Cannot update the try branch: {reason}");
+        render(template, &[("pr", &pr.to_string()), ("reason", reason)])
+    }
+
+    pub fn merge_conflict(&self, pr: PullRequestNumber, branch: &str, base: &str) -> String {
+        let template = self.merge_conflict.as_deref().unwrap_or(MERGE_CONFLICT);
+        render(
+            template,
+            &[("pr", &pr.to_string()), ("branch", branch), ("base", base)],
+        )
+    }
+
+    pub fn permission_denied(&self, user: &str, reason: &DenialReason) -> String {
+        let template = self
+            .permission_denied
+            .as_deref()
+            .unwrap_or("@{user}: :key: Insufficient privileges: {reason}");
+        render(template, &[("user", user), ("reason", &reason.to_string())])
+    }
+
+    pub fn merge_queue_left(&self, pr: PullRequestNumber, reason: &str, checks: &str) -> String {
+        let template = self.merge_queue_left.as_deref().unwrap_or(
+            ":broken_heart: This pull request left the merge queue because {reason}.\n\n{checks}",
+        );
+        render(
+            template,
+            &[
+                ("pr", &pr.to_string()),
+                ("reason", reason),
+                ("checks", checks),
+            ],
+        )
+    }
+
+    pub fn merge_group_discarded(
+        &self,
+        pr: PullRequestNumber,
+        reason: &str,
+        checks: &str,
+    ) -> String {
+        let template = self.merge_group_discarded.as_deref().unwrap_or(
+            ":broken_heart: The merge group of #{pr}, which also tested this pull request, was {reason}.\n\n{checks}",
+        );
+        render(
+            template,
+            &[
+                ("pr", &pr.to_string()),
+                ("reason", reason),
+                ("checks", checks),
+            ],
+        )
+    }
+
+    /// Summary of a merged PR, the default one leaves out the values that are unknown (empty).
+    pub fn merge_queue_merged(
+        &self,
+        pr: PullRequestNumber,
+        sha: &str,
+        branch: &str,
+        workflows: &str,
+        approvers: &str,
+        time_in_queue: &str,
+    ) -> String {
+        let values = [
+            ("pr", pr.to_string()),
+            ("sha", sha.to_owned()),
+            ("branch", branch.to_owned()),
+            ("workflows", workflows.to_owned()),
+            ("approvers", approvers.to_owned()),
+            ("time_in_queue", time_in_queue.to_owned()),
+        ];
+        let values: Vec<(&str, &str)> = values
+            .iter()
+            .map(|(name, value)| (*name, value.as_str()))
+            .collect();
+        if let Some(template) = &self.merge_queue_merged {
+            return render(template, &values);
+        }
+        let mut template = ":sunny: Test successful".to_string();
+        if !workflows.is_empty() {
+            template += " - {workflows}";
+        }
+        if !approvers.is_empty() {
+            template += "\nApproved by: {approvers}";
+        }
+        if !time_in_queue.is_empty() {
+            template += "\nTime in queue: {time_in_queue}";
+        }
+        template += "\nPushed {sha} to `{branch}`.";
+        render(&template, &values)
+    }
+}
+
+#[test]
+fn default_messages() {
+    let messages = Messages::default();
+    assert_eq!(
+        messages.try_started(1, "abc", "def"),
+        ":hourglass: Trying commit abc with merge def…"
+    );
+    assert_eq!(
+        messages.permission_denied("alice", &DenialReason::NotTryUser),
+        "@alice: :key: Insufficient privileges: not in try users"
+    );
+    assert!(messages
+        .merge_conflict(1, "feature", "main")
+        .contains("git checkout feature"));
+    assert_eq!(
+        messages.ping(&GithubRepo::new("servo", "servo"), "alice"),
+        ":sleepy: I'm awake I'm awake"
+    );
+}
+
+#[test]
+fn custom_messages() {
+    let mut messages = Messages {
+        ping: Some("Hi {user}".to_string()),
+        merge_conflict: Some("#{pr}: rebase {branch} on {base}, {unknown}".to_string()),
+        ..Default::default()
+    };
+    messages.merge(Messages {
+        ping: Some("Hello {user}".to_string()),
+        ..Default::default()
+    });
+    assert_eq!(
+        messages.ping(&GithubRepo::new("servo", "servo"), "alice"),
+        "Hello alice"
+    );
+    assert_eq!(
+        messages.merge_conflict(3, "feature", "main"),
+        "#3: rebase feature on main, {unknown}"
+    );
+}
+
+#[test]
+fn merge_queue_merged_message() {
+    let messages = Messages::default();
+    assert_eq!(
+        messages.merge_queue_merged(3, "abc", "main", "", "@alice", "1m"),
+        ":sunny: Test successful\nApproved by: @alice\nTime in queue: 1m\nPushed abc to `main`."
+    );
+    let messages = Messages {
+        merge_queue_merged: Some("#{pr} merged into {branch} ({workflows})".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(
+        messages.merge_queue_merged(3, "abc", "main", "", "@alice", "1m"),
+        "#3 merged into main ()"
+    );
+}
+
+#[test]
+fn render_single_pass() {
+    assert_eq!(
+        render(
+            "{title} by {user} {unknown} {{user}} {",
+            &[("title", "Fix {user}"), ("user", "alice")]
+        ),
+        "Fix {user} by alice {unknown} {alice} {"
+    );
+}
+
+#[test]
+fn message_keys() {
+    // Every field is listed, so that a new one cannot be forgotten in `MESSAGE_KEYS`
+    let messages = Messages {
+        ping: Some(String::new()),
+        try_started: Some(String::new()),
+        try_in_progress: Some(String::new()),
+        try_timed_out: Some(String::new()),
+        branch_update_failed: Some(String::new()),
+        merge_conflict: Some(String::new()),
+        permission_denied: Some(String::new()),
+        merge_queue_left: Some(String::new()),
+        merge_group_discarded: Some(String::new()),
+        merge_queue_merged: Some(String::new()),
+    };
+    let Ok(toml::Value::Table(table)) = toml::Value::try_from(messages) else {
+        panic!("messages are not a table");
+    };
+    let mut keys: Vec<&str> = table.keys().map(String::as_str).collect();
+    let mut known = MESSAGE_KEYS.to_vec();
+    keys.sort_unstable();
+    known.sort_unstable();
+    assert_eq!(keys, known);
+}
@@ -21,3 +21,30 @@ pub struct ListCheckRuns {
     pub total_count: u64,
     pub check_runs: Vec<CheckRun>,
 }
+
+/// Output of a check run, shown on its page.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CheckRunOutput {
+    pub title: String,
+    pub summary: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub annotations: Vec<CheckRunAnnotation>,
+}
+
+/// Annotation of a line of a file in a check run output.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CheckRunAnnotation {
+    pub path: String,
+    pub start_line: usize,
+    pub end_line: usize,
+    pub annotation_level: AnnotationLevel,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnnotationLevel {
+    Notice,
+    Warning,
+    Failure,
+}
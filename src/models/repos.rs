@@ -344,3 +344,14 @@ pub struct MergeCommit {
     pub html_url: String,
     pub comments_url: String,
 }
+
+/// A rule (from a ruleset) that applies to a branch.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct BranchRule {
+    /// Kind of the rule. Example: merge_queue
+    #[serde(rename = "type")]
+    pub rule_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ruleset_id: Option<u64>,
+}
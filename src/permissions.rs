@@ -1,8 +1,13 @@
 //! Permission parsing go as follows:
-//! configs, (org not impl yet)
+//! configs, then organization teams and repository collaborators
+
+use std::fmt::{Display, Formatter};
 
 use crate::config::Config;
+use crate::github::client::GitHubClient;
+use crate::github::GithubRepo;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PermissionType {
     /// Can perform commands like r+.
     Review,
@@ -10,15 +15,86 @@ pub enum PermissionType {
     Try,
 }
 
+/// Permission of a collaborator on a repository, ordered from the weakest.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum RepoPermission {
+    Read,
+    Triage,
+    Write,
+    Maintain,
+    Admin,
+}
+
+/// Why a permission was granted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrantReason {
+    /// User is listed in the config.
+    Listed,
+    /// User is a member of the team (`org/team`).
+    TeamMember(String),
+    /// User is a collaborator with (at least) `min_repo_permission`.
+    Collaborator(RepoPermission),
+}
+
+/// Why a permission was denied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DenialReason {
+    /// Review commands need a reviewer.
+    NotReviewer,
+    /// Try builds need a try user (or a reviewer).
+    NotTryUser,
+    /// The PR was not delegated to the user.
+    NotDelegated,
+    /// Only the author of the PR may use the command (e.g. `r-` of own approval).
+    NotAuthor,
+    /// The tree is closed, nothing can be approved.
+    TreeClosed,
+}
+
+impl DenialReason {
+    fn of(permission: PermissionType) -> Self {
+        match permission {
+            PermissionType::Review => DenialReason::NotReviewer,
+            PermissionType::Try => DenialReason::NotTryUser,
+        }
+    }
+}
+
+impl Display for DenialReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DenialReason::NotReviewer => "not a reviewer",
+            DenialReason::NotTryUser => "not in try users",
+            DenialReason::NotDelegated => "not delegated",
+            DenialReason::NotAuthor => "only the PR author may do this",
+            DenialReason::TreeClosed => "the tree is closed",
+        })
+    }
+}
+
+/// Outcome of a permission check, used both for replies and audit logs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PermissionDecision {
+    Granted(GrantReason),
+    Denied(DenialReason),
+}
+
 /// Decides if a GitHub user can perform various actions using the bot.
-#[async_trait::async_trait]
+#[async_trait::async_trait(?Send)]
 pub trait PermissionResolver {
-    async fn has_permission(&self, username: &str, permission: PermissionType) -> bool;
+    async fn check_permission(
+        &mut self,
+        username: &str,
+        permission: PermissionType,
+    ) -> PermissionDecision;
 }
 
-#[async_trait::async_trait]
-impl PermissionResolver for Config {
-    async fn has_permission(&self, username: &str, permission: PermissionType) -> bool {
+impl Config {
+    /// Checks the static lists of users.
+    fn lists_user(&self, username: &str, permission: PermissionType) -> bool {
         match permission {
             PermissionType::Review => self.reviewers.contains(username),
             PermissionType::Try => {
@@ -27,3 +103,177 @@ impl PermissionResolver for Config {
         }
     }
 }
+
+#[async_trait::async_trait(?Send)]
+impl PermissionResolver for Config {
+    async fn check_permission(
+        &mut self,
+        username: &str,
+        permission: PermissionType,
+    ) -> PermissionDecision {
+        if self.lists_user(username, permission) {
+            PermissionDecision::Granted(GrantReason::Listed)
+        } else {
+            PermissionDecision::Denied(DenialReason::of(permission))
+        }
+    }
+}
+
+/// Resolves permissions from the config, then from organization teams
+/// and permissions of repository collaborators.
+pub struct GitHubPermissionResolver<'a, C: GitHubClient> {
+    client: &'a mut C,
+    repo: &'a GithubRepo,
+    config: &'a Config,
+}
+
+impl<'a, C: GitHubClient> GitHubPermissionResolver<'a, C> {
+    pub fn new(client: &'a mut C, repo: &'a GithubRepo, config: &'a Config) -> Self {
+        Self {
+            client,
+            repo,
+            config,
+        }
+    }
+
+    async fn is_team_member(&mut self, team: &str, username: &str) -> bool {
+        if let Some(member) = self.client.cache().team_membership(team, username).await {
+            return member;
+        }
+        let Some((org, slug)) = team.split_once('/') else {
+            tracing::warn!("Invalid team {team}, expected `org/team`");
+            return false;
+        };
+        match self.client.is_team_member(org, slug, username).await {
+            Ok(member) => {
+                self.client
+                    .cache()
+                    .set_team_membership(team, username, member)
+                    .await;
+                member
+            }
+            Err(error) => {
+                tracing::warn!("Cannot check membership of {username} in {team}: {error:?}");
+                false
+            }
+        }
+    }
+
+    async fn repo_permission(&mut self, username: &str) -> Option<RepoPermission> {
+        if let Some(permission) = self
+            .client
+            .cache()
+            .repo_permission(self.repo, username)
+            .await
+        {
+            return permission;
+        }
+        match self
+            .client
+            .get_collaborator_permission(self.repo, username)
+            .await
+        {
+            Ok(permission) => {
+                self.client
+                    .cache()
+                    .set_repo_permission(self.repo, username, permission)
+                    .await;
+                permission
+            }
+            Err(error) => {
+                tracing::warn!("Cannot get permission of {username}: {error:?}");
+                None
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<'a, C: GitHubClient> PermissionResolver for GitHubPermissionResolver<'a, C> {
+    async fn check_permission(
+        &mut self,
+        username: &str,
+        permission: PermissionType,
+    ) -> PermissionDecision {
+        let config = self.config;
+        if config.lists_user(username, permission) {
+            return PermissionDecision::Granted(GrantReason::Listed);
+        }
+
+        let teams = match permission {
+            PermissionType::Review => config.reviewer_teams.iter().collect::<Vec<_>>(),
+            PermissionType::Try => config
+                .reviewer_teams
+                .iter()
+                .chain(config.try_teams.iter())
+                .collect(),
+        };
+        for team in teams {
+            if self.is_team_member(team, username).await {
+                return PermissionDecision::Granted(GrantReason::TeamMember(team.clone()));
+            }
+        }
+
+        if let Some(min) = config.min_repo_permission {
+            if let Some(permission) = self.repo_permission(username).await {
+                if permission >= min {
+                    return PermissionDecision::Granted(GrantReason::Collaborator(permission));
+                }
+            }
+        }
+        PermissionDecision::Denied(DenialReason::of(permission))
+    }
+}
+
+#[tokio::test]
+async fn config_decisions() {
+    let mut config: Config = toml::from_str(
+        r#"
+reviewers = ["alice"]
+try_users = ["bob"]
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+        config
+            .check_permission("alice", PermissionType::Review)
+            .await,
+        PermissionDecision::Granted(GrantReason::Listed)
+    );
+    assert_eq!(
+        config.check_permission("bob", PermissionType::Try).await,
+        PermissionDecision::Granted(GrantReason::Listed)
+    );
+    assert_eq!(
+        config.check_permission("bob", PermissionType::Review).await,
+        PermissionDecision::Denied(DenialReason::NotReviewer)
+    );
+    assert_eq!(
+        config.check_permission("eve", PermissionType::Try).await,
+        PermissionDecision::Denied(DenialReason::NotTryUser)
+    );
+}
+
+#[test]
+fn denial_replies() {
+    let messages = crate::messages::Messages::default();
+    for (reason, text) in [
+        (DenialReason::NotReviewer, "not a reviewer"),
+        (DenialReason::NotTryUser, "not in try users"),
+        (DenialReason::NotDelegated, "not delegated"),
+        (DenialReason::NotAuthor, "only the PR author may do this"),
+        (DenialReason::TreeClosed, "the tree is closed"),
+    ] {
+        assert_eq!(
+            messages.permission_denied("eve", &reason),
+            format!("@eve: :key: Insufficient privileges: {text}")
+        );
+    }
+}
+
+#[test]
+fn permission_order() {
+    assert!(RepoPermission::Admin > RepoPermission::Maintain);
+    assert!(RepoPermission::Write > RepoPermission::Triage);
+    assert!(RepoPermission::Read < RepoPermission::Write);
+}
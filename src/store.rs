@@ -0,0 +1,249 @@
+//! Persistent state of the bot, kept in Workers KV.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use worker::kv::KvStore;
+use worker::Env;
+
+use crate::github::webhook::GitHubWebhook;
+use crate::github::{CommitSha, GithubRepo, PullRequestNumber};
+use crate::models::InstallationId;
+
+/// Binding of the KV namespace (see `wrangler.toml`)
+const KV_BINDING: &str = "BORS";
+
+/// Shortest expiration supported by KV, in seconds
+pub const MIN_TTL: u64 = 60;
+
+/// Failure of Workers KV, usually temporary.
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct StoreError(String);
+
+/// Handle to the bot's KV namespace.
+///
+/// If the namespace is not bound, reads return nothing and writes are dropped,
+/// so the bot still works, just without any state.
+#[derive(Clone, Default)]
+pub struct Store(Option<KvStore>);
+
+impl Store {
+    pub fn new(env: &Env) -> Self {
+        match env.kv(KV_BINDING) {
+            Ok(kv) => Self(Some(kv)),
+            Err(error) => {
+                tracing::warn!("KV namespace {KV_BINDING} is not bound: {error:?}");
+                Self(None)
+            }
+        }
+    }
+
+    /// Reads JSON value stored under `key`.
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        let Some(kv) = &self.0 else {
+            return Ok(None);
+        };
+        let text = kv
+            .get(key)
+            .text()
+            .await
+            .map_err(|error| StoreError(format!("Cannot read {key} from KV: {error:?}")))?;
+        match text {
+            Some(text) => Ok(Some(serde_json::from_str(&text)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Stores `value` as JSON under `key`.
+    pub async fn put<T: Serialize>(&self, key: &str, value: &T) -> Result<()> {
+        self.put_inner(key, value, None).await
+    }
+
+    /// Stores `value` as JSON under `key`, it expires after `ttl` seconds
+    /// (at least [`MIN_TTL`]).
+    pub async fn put_with_ttl<T: Serialize>(&self, key: &str, value: &T, ttl: u64) -> Result<()> {
+        self.put_inner(key, value, Some(ttl.max(MIN_TTL))).await
+    }
+
+    async fn put_inner<T: Serialize>(&self, key: &str, value: &T, ttl: Option<u64>) -> Result<()> {
+        let Some(kv) = &self.0 else {
+            return Ok(());
+        };
+        let mut put = kv
+            .put(key, serde_json::to_string(value)?)
+            .map_err(|error| StoreError(format!("Cannot write {key} to KV: {error:?}")))?;
+        if let Some(ttl) = ttl {
+            put = put.expiration_ttl(ttl);
+        }
+        put.execute()
+            .await
+            .map_err(|error| StoreError(format!("Cannot write {key} to KV: {error:?}")).into())
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<()> {
+        let Some(kv) = &self.0 else {
+            return Ok(());
+        };
+        kv.delete(key)
+            .await
+            .map_err(|error| StoreError(format!("Cannot delete {key} from KV: {error:?}")).into())
+    }
+
+    /// Lists all keys starting with `prefix`.
+    pub async fn keys(&self, prefix: &str) -> Result<Vec<String>> {
+        let Some(kv) = &self.0 else {
+            return Ok(Vec::new());
+        };
+        let mut keys = Vec::new();
+        let mut cursor = None;
+        loop {
+            let mut list = kv.list().prefix(prefix.to_owned());
+            if let Some(cursor) = cursor {
+                list = list.cursor(cursor);
+            }
+            let response = list
+                .execute()
+                .await
+                .map_err(|error| StoreError(format!("Cannot list {prefix} in KV: {error:?}")))?;
+            keys.extend(response.keys.into_iter().map(|key| key.name));
+            if response.list_complete {
+                break;
+            }
+            cursor = response.cursor;
+        }
+        Ok(keys)
+    }
+}
+
+/// A repository the bot's Github App is installed on.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct InstalledRepository {
+    pub installation: InstallationId,
+}
+
+/// Prefix of [`InstalledRepository`] keys
+pub const INSTALLED_REPOSITORY_PREFIX: &str = "repo:";
+
+pub fn installed_repository_key(repo: &GithubRepo) -> String {
+    format!("{INSTALLED_REPOSITORY_PREFIX}{repo}")
+}
+
+/// Setup issues are opened again after this time (in seconds) if the repository
+/// is added to the bot again and still is not set up.
+pub const SETUP_ISSUE_TTL: u64 = 30 * 24 * 60 * 60;
+
+/// An issue asking to finish the setup of a repository, opened only once.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct SetupIssue {
+    pub opened_at: DateTime<Utc>,
+}
+
+pub fn setup_issue_key(repo: &GithubRepo) -> String {
+    format!("setup-issue:{repo}")
+}
+
+/// Prefix of [`PullRequestModel`](crate::github::misc::PullRequestModel) keys
+pub const PULL_REQUEST_PREFIX: &str = "pr:";
+
+pub fn pull_request_key(repo: &GithubRepo, pr: PullRequestNumber) -> String {
+    format!("{PULL_REQUEST_PREFIX}{repo}#{pr}")
+}
+
+/// Merge queue records are dropped after this time (in seconds) if the merge never happens.
+pub const MERGE_QUEUE_TTL: u64 = 7 * 24 * 60 * 60;
+
+/// A merge group that GitHub merge queue tested, keyed by its head commit.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct QueuedMergeGroup {
+    pub pull_request: PullRequestNumber,
+    /// Parent commit of the group, used to find the groups ahead of it.
+    #[serde(default)]
+    pub base_sha: Option<CommitSha>,
+}
+
+pub fn merge_group_key(repo: &GithubRepo, sha: &CommitSha) -> String {
+    format!("group:{repo}:{sha}")
+}
+
+/// A pull request waiting in GitHub merge queue.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct QueuedPullRequest {
+    pub enqueued_at: DateTime<Utc>,
+}
+
+/// Prefix of [`QueuedPullRequest`] keys
+pub const QUEUED_PULL_REQUEST_PREFIX: &str = "queued:";
+
+pub fn queued_pull_request_key(repo: &GithubRepo, pr: PullRequestNumber) -> String {
+    format!("{QUEUED_PULL_REQUEST_PREFIX}{repo}#{pr}")
+}
+
+/// Reported merge conflicts are forgotten after this time (in seconds).
+pub const MERGE_CONFLICT_TTL: u64 = 30 * 24 * 60 * 60;
+
+/// A merge conflict of a pull request that was already reported,
+/// kept until the pull request becomes mergeable again.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct ReportedMergeConflict {
+    /// Base branch the pull request conflicts with.
+    pub base: String,
+}
+
+pub fn merge_conflict_key(repo: &GithubRepo, pr: PullRequestNumber) -> String {
+    format!("conflict:{repo}#{pr}")
+}
+
+/// Processed webhook deliveries are forgotten after this time (in seconds),
+/// GitHub allows redelivering webhooks from the last 3 days.
+pub const DELIVERY_TTL: u64 = 3 * 24 * 60 * 60;
+
+/// Deliveries that are being handled are forgotten after this time (in seconds),
+/// so that the handling is retried if the worker crashed.
+/// Keep it below `retry_delay` of the queue consumer (see `wrangler.toml`).
+pub const DELIVERY_IN_PROGRESS_TTL: u64 = 4 * 60;
+
+/// A webhook delivery that was already handled (or is being handled).
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct ProcessedDelivery {
+    pub processed_at: DateTime<Utc>,
+    #[serde(default)]
+    pub state: DeliveryState,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryState {
+    /// The delivery is being handled right now (or the handling crashed).
+    InProgress,
+    /// The delivery was handled successfully.
+    #[default]
+    Done,
+    /// Handling failed with a temporary error, so the next delivery
+    /// (a retry from the queue or a manual redelivery) handles it again.
+    Retry,
+}
+
+pub fn delivery_key(delivery: &str) -> String {
+    format!("delivery:{delivery}")
+}
+
+/// Webhooks deferred by a rate limit are dropped after this time (in seconds),
+/// the limits reset within an hour.
+pub const DEFERRED_WEBHOOK_TTL: u64 = 24 * 60 * 60;
+
+/// A webhook whose handling hit the API rate limit, queued again by the cron
+/// trigger once the limit resets.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct DeferredWebhook {
+    pub webhook: GitHubWebhook,
+    pub retry_at: DateTime<Utc>,
+}
+
+/// Prefix of [`DeferredWebhook`] keys
+pub const DEFERRED_WEBHOOK_PREFIX: &str = "deferred:";
+
+pub fn deferred_webhook_key(delivery: &str) -> String {
+    format!("{DEFERRED_WEBHOOK_PREFIX}{delivery}")
+}
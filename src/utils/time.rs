@@ -0,0 +1,31 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+/// Current time.
+///
+/// `std::time::SystemTime` is not available on `wasm32-unknown-unknown`,
+/// so the clock is read through `time` (which uses JS `Date`).
+pub fn now() -> DateTime<Utc> {
+    let now = time::OffsetDateTime::now_utc();
+    Utc.timestamp_opt(now.unix_timestamp(), now.nanosecond())
+        .single()
+        .unwrap_or_default()
+}
+
+/// Human readable duration, e.g. `1h 2m 3s`.
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.num_seconds().max(0);
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    match (hours, minutes) {
+        (0, 0) => format!("{seconds}s"),
+        (0, _) => format!("{minutes}m {seconds}s"),
+        _ => format!("{hours}h {minutes}m {seconds}s"),
+    }
+}
+
+#[test]
+fn durations() {
+    assert_eq!(format_duration(Duration::seconds(42)), "42s");
+    assert_eq!(format_duration(Duration::seconds(125)), "2m 5s");
+    assert_eq!(format_duration(Duration::seconds(3723)), "1h 2m 3s");
+    assert_eq!(format_duration(Duration::seconds(-5)), "0s");
+}